@@ -3,43 +3,210 @@
  * License: MIT
  */
 
+use std::fmt;
+use std::io;
 use std::time::Duration;
 
-use anyhow::{Result, anyhow};
+use nanorand::{Rng, WyRand};
 use serde::{Deserialize, Serialize};
-use ureq::{Agent, Error};
+use time::OffsetDateTime;
+use ureq::http::Response;
+use ureq::{Agent, Body, Error};
 
 use crate::config::ApiConfig;
 use crate::http::build_agent;
 use crate::model::{PartyId, UserId};
+use crate::retry::RetryPolicy;
 
 pub(crate) struct ApiClient {
     pub base_url: String,
     pub client_token: String,
     pub party_id: PartyId,
     agent: Agent,
+    timeout: Duration,
+    retry_policy: RetryPolicy,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct StatusUpdate {
     pub user_id: UserId,
     pub party_id: String,
     pub whereabouts_name: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub occurred_at: OffsetDateTime,
+    /// Identifies the update across repeated submissions
+    #[serde(default = "generate_idempotency_key")]
+    pub idempotency_key: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// Health report of the client
+#[derive(Debug, Serialize)]
+pub(crate) struct Heartbeat {
+    pub uptime_in_seconds: u64,
+    pub version: &'static str,
+    /// Not reported if the reader is not used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_connected: Option<bool>,
+    /// Not reported if no buttons are used (e.g. in simulation)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buttons_connected: Option<bool>,
+    pub audio_ok: bool,
+    pub queued_status_updates: usize,
+}
+
+/// Body of a status update request
+#[derive(Serialize)]
+struct StatusUpdateRequest<'a> {
+    user_id: &'a str,
+    party_id: &'a str,
+    whereabouts_name: &'a str,
+    #[serde(with = "time::serde::rfc3339")]
+    occurred_at: OffsetDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct TagDetails {
     pub identifier: String,
     pub user: TagUser,
     pub sound_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct TagUser {
     pub id: UserId,
     pub screen_name: Option<String>,
 }
 
+/// An error that occurred when calling the API
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    /// The client token was not accepted (e.g. because the client has
+    /// been removed).
+    Unauthorized(ErrorDetails),
+    Forbidden(ErrorDetails),
+    NotFound(ErrorDetails),
+    /// The request was rejected as invalid (e.g. unknown whereabouts).
+    UnprocessableEntity(ErrorDetails),
+    ServerError(ErrorDetails),
+    UnexpectedStatus(ErrorDetails),
+    /// The API did not respond in time.
+    Timeout(Error),
+    /// The API could not be reached.
+    Network(Error),
+    /// The response could not be read or parsed.
+    InvalidResponse(Error),
+}
+
+impl ApiError {
+    /// Return whether the failure might go away when retrying later.
+    pub(crate) fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::ServerError(_) | Self::Timeout(_) | Self::Network(_)
+        )
+    }
+
+    fn from_status(status: u16, message: Option<String>) -> Self {
+        let details = ErrorDetails { status, message };
+        match status {
+            401 => Self::Unauthorized(details),
+            403 => Self::Forbidden(details),
+            404 => Self::NotFound(details),
+            422 => Self::UnprocessableEntity(details),
+            500..=599 => Self::ServerError(details),
+            _ => Self::UnexpectedStatus(details),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unauthorized(details) => write!(f, "API error: Client not authorized {details}"),
+            Self::Forbidden(details) => write!(f, "API error: Forbidden {details}"),
+            Self::NotFound(details) => write!(f, "API error: Not found {details}"),
+            Self::UnprocessableEntity(details) => {
+                write!(f, "API error: Request rejected {details}")
+            }
+            Self::ServerError(details) => write!(f, "API error: Server error {details}"),
+            Self::UnexpectedStatus(details) => write!(f, "API error: Unexpected status {details}"),
+            Self::Timeout(e) => write!(f, "Network error: Timeout: {}", e),
+            Self::Network(e) => write!(f, "Network error: {}", e),
+            Self::InvalidResponse(e) => write!(f, "Invalid API response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Status code and error message (if any) of a failed request
+#[derive(Debug)]
+pub(crate) struct ErrorDetails {
+    pub status: u16,
+    pub message: Option<String>,
+}
+
+impl fmt::Display for ErrorDetails {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(HTTP {})", self.status)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// JSON body of an error response
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: Option<String>,
+    message: Option<String>,
+    description: Option<String>,
+}
+
+/// Turn a response with an error status, as well as transport failures,
+/// into an error.
+pub(crate) fn check_response(
+    result: Result<Response<Body>, Error>,
+) -> Result<Response<Body>, ApiError> {
+    let mut response = match result {
+        Ok(response) => response,
+        Err(e @ Error::Timeout(_)) => return Err(ApiError::Timeout(e)),
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::TimedOut => {
+            return Err(ApiError::Timeout(Error::Io(e)));
+        }
+        Err(Error::StatusCode(status)) => return Err(ApiError::from_status(status, None)),
+        Err(e) => return Err(ApiError::Network(e)),
+    };
+
+    let status = response.status().as_u16();
+    if status < 400 {
+        return Ok(response);
+    }
+
+    let message = response
+        .body_mut()
+        .read_to_string()
+        .ok()
+        .and_then(|body| parse_error_message(&body));
+    Err(ApiError::from_status(status, message))
+}
+
+fn parse_error_message(body: &str) -> Option<String> {
+    let body = body.trim();
+    if body.is_empty() {
+        return None;
+    }
+
+    match serde_json::from_str::<ErrorBody>(body) {
+        Ok(error_body) => error_body
+            .error
+            .or(error_body.message)
+            .or(error_body.description),
+        Err(_) => Some(body.chars().take(200).collect()),
+    }
+}
+
 impl ApiClient {
     pub(crate) fn new(config: &ApiConfig, party_id: PartyId) -> Self {
         Self {
@@ -50,73 +217,173 @@ impl ApiClient {
                 Duration::from_secs(config.timeout_in_seconds),
                 !config.tls_verify,
             ),
+            timeout: Duration::from_secs(config.timeout_in_seconds),
+            retry_policy: RetryPolicy::new(&config.retry),
         }
     }
 
-    pub(crate) fn sign_on(&self) -> Result<()> {
+    /// Limit an attempt to the time left until the retry deadline.
+    fn attempt_timeout(&self, remaining: Duration) -> Duration {
+        self.timeout.min(remaining)
+    }
+
+    pub(crate) fn sign_on(&self) -> Result<(), ApiError> {
         let url = format!("{}/client/sign_on", self.base_url);
 
-        match self
-            .agent
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.client_token))
-            .send_empty()
-        {
-            Ok(_) => Ok(()),
-            Err(Error::StatusCode(code)) => Err(anyhow!("API error: {}", code)),
-            Err(e) => Err(anyhow!("Network error: {}", e)),
-        }
+        self.retry_policy.run("sign on", |remaining| {
+            check_response(
+                self.agent
+                    .post(&url)
+                    .config()
+                    .timeout_global(Some(self.attempt_timeout(remaining)))
+                    .build()
+                    .header("Authorization", format!("Bearer {}", self.client_token))
+                    .send_empty(),
+            )?;
+            Ok(())
+        })
     }
 
-    pub(crate) fn sign_off(&self) -> Result<()> {
+    pub(crate) fn sign_off(&self) -> Result<(), ApiError> {
         let url = format!("{}/client/sign_off", self.base_url);
 
-        match self
-            .agent
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.client_token))
-            .send_empty()
-        {
-            Ok(_) => Ok(()),
-            Err(Error::StatusCode(code)) => Err(anyhow!("API error: {}", code)),
-            Err(e) => Err(anyhow!("Network error: {}", e)),
-        }
+        self.retry_policy.run("sign off", |remaining| {
+            check_response(
+                self.agent
+                    .post(&url)
+                    .config()
+                    .timeout_global(Some(self.attempt_timeout(remaining)))
+                    .build()
+                    .header("Authorization", format!("Bearer {}", self.client_token))
+                    .send_empty(),
+            )?;
+            Ok(())
+        })
     }
 
-    pub(crate) fn get_tag_details(&self, tag: &str) -> Result<Option<TagDetails>> {
+    pub(crate) fn get_tag_details(&self, tag: &str) -> Result<Option<TagDetails>, ApiError> {
         let url = format!("{}/tags/{}", &self.base_url, tag);
 
-        match self
-            .agent
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.client_token))
-            .call()
-        {
-            Ok(mut response) => response
+        self.retry_policy.run("request tag details", |remaining| {
+            match check_response(
+                self.agent
+                    .get(&url)
+                    .config()
+                    .timeout_global(Some(self.attempt_timeout(remaining)))
+                    .build()
+                    .header("Authorization", format!("Bearer {}", self.client_token))
+                    .call(),
+            ) {
+                Ok(mut response) => response
+                    .body_mut()
+                    .read_json::<TagDetails>()
+                    .map_err(ApiError::InvalidResponse)
+                    .map(Some),
+                Err(ApiError::NotFound(_)) => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    pub(crate) fn get_all_tag_details(&self) -> Result<Vec<TagDetails>, ApiError> {
+        let url = format!("{}/tags", &self.base_url);
+
+        self.retry_policy
+            .run("request all tag details", |remaining| {
+                check_response(
+                    self.agent
+                        .get(&url)
+                        .config()
+                        .timeout_global(Some(self.attempt_timeout(remaining)))
+                        .build()
+                        .header("Authorization", format!("Bearer {}", self.client_token))
+                        .call(),
+                )?
                 .body_mut()
-                .read_json::<TagDetails>()
-                .map_err(|e| anyhow!("JSON error: {}", e))
-                .map(Some),
-            Err(Error::StatusCode(404)) => Ok(None),
-            Err(e) => Err(anyhow!("Network error: {}", e)),
+                .read_json::<Vec<TagDetails>>()
+                .map_err(ApiError::InvalidResponse)
+            })
+    }
+
+    pub(crate) fn build_status_update(
+        &self,
+        user_id: &UserId,
+        whereabouts_name: &str,
+    ) -> StatusUpdate {
+        StatusUpdate {
+            user_id: user_id.to_string(),
+            party_id: self.party_id.to_string(),
+            whereabouts_name: whereabouts_name.to_string(),
+            occurred_at: OffsetDateTime::now_utc(),
+            idempotency_key: generate_idempotency_key(),
         }
     }
 
-    pub(crate) fn update_status(&self, user_id: &UserId, whereabouts_name: &str) -> Result<()> {
+    /// Submit a status update. Retries carry the update's idempotency key
+    /// so the API can recognize updates it has already applied.
+    pub(crate) fn update_status(&self, status_update: &StatusUpdate) -> Result<(), ApiError> {
+        self.retry_policy.run("update status", |remaining| {
+            self.submit_status_update(status_update, self.attempt_timeout(remaining))
+        })
+    }
+
+    /// Submit a status update once, without retrying (e.g. because
+    /// failed updates are queued instead).
+    pub(crate) fn update_status_once(&self, status_update: &StatusUpdate) -> Result<(), ApiError> {
+        self.submit_status_update(status_update, self.timeout)
+    }
+
+    fn submit_status_update(
+        &self,
+        status_update: &StatusUpdate,
+        timeout: Duration,
+    ) -> Result<(), ApiError> {
         let url = format!("{}/statuses", self.base_url);
 
-        match self
-            .agent
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.client_token))
-            .send_json(StatusUpdate {
-                user_id: user_id.to_string(),
-                party_id: self.party_id.to_string(),
-                whereabouts_name: whereabouts_name.to_string(),
-            }) {
-            Ok(_) => Ok(()),
-            Err(Error::StatusCode(code)) => Err(anyhow!("API error: {}", code)),
-            Err(e) => Err(anyhow!("Network error: {}", e)),
-        }
+        let request = StatusUpdateRequest {
+            user_id: &status_update.user_id,
+            party_id: &status_update.party_id,
+            whereabouts_name: &status_update.whereabouts_name,
+            occurred_at: status_update.occurred_at,
+        };
+
+        check_response(
+            self.agent
+                .post(&url)
+                .config()
+                .timeout_global(Some(timeout))
+                .build()
+                .header("Authorization", format!("Bearer {}", self.client_token))
+                .header("Idempotency-Key", &status_update.idempotency_key)
+                .send_json(&request),
+        )?;
+        Ok(())
     }
+
+    /// Report the client's health. The endpoint is relative to the base
+    /// URL.
+    pub(crate) fn send_heartbeat(
+        &self,
+        endpoint: &str,
+        heartbeat: &Heartbeat,
+    ) -> Result<(), ApiError> {
+        let url = format!("{}/{}", self.base_url, endpoint.trim_start_matches('/'));
+
+        check_response(
+            self.agent
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", self.client_token))
+                .send_json(heartbeat),
+        )?;
+        Ok(())
+    }
+}
+
+fn generate_idempotency_key() -> String {
+    let mut rng = WyRand::new();
+    format!(
+        "{:016x}{:016x}",
+        rng.generate::<u64>(),
+        rng.generate::<u64>()
+    )
 }
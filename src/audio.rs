@@ -3,68 +3,613 @@
  * License: MIT
  */
 
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io::BufReader;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-use anyhow::Result;
-use anyhow::ensure;
-use rodio::{Decoder, OutputStream, OutputStreamBuilder, Sink};
+use anyhow::{Context, Result, anyhow, ensure};
+use flume::{Receiver, RecvTimeoutError, Sender, WeakSender};
+use rodio::buffer::SamplesBuffer;
+use rodio::source::SineWave;
+use rodio::{Decoder, OutputStream, OutputStreamBuilder, Sink, Source};
 
+use crate::config::AudioConfig;
+use crate::heartbeat::Health;
+use crate::quiethours::QuietHours;
+use crate::tts::SpeechSynthesizer;
+
+/// Interval at which the worker checks whether the current sound has
+/// finished playing
+const PLAYBACK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Upper limit for the master volume and per-sound gains, to protect
+/// speakers and ears from typos
+const MAX_VOLUME: f32 = 2.0;
+
+/// Beep played in place of sounds during quiet hours (if configured)
+const BEEP_FREQUENCY: f32 = 880.0;
+const BEEP_DURATION: Duration = Duration::from_millis(150);
+
+/// Extensions of the sound file formats enabled at compile time, in
+/// order of preference
+const SOUND_FILE_EXTENSIONS: &[&str] = &[
+    #[cfg(feature = "ogg")]
+    "ogg",
+    #[cfg(feature = "flac")]
+    "flac",
+    #[cfg(feature = "wav")]
+    "wav",
+    #[cfg(feature = "mp3")]
+    "mp3",
+];
+
+/// Sound files, optionally decoded into memory in advance
 pub(crate) struct SoundLibrary {
     path: PathBuf,
+    preloaded: HashMap<String, SamplesBuffer>,
 }
 
 impl SoundLibrary {
-    fn new(path: PathBuf) -> SoundLibrary {
-        SoundLibrary { path }
+    pub(crate) fn new(path: PathBuf) -> SoundLibrary {
+        SoundLibrary {
+            path,
+            preloaded: HashMap::new(),
+        }
+    }
+
+    /// Decode the sounds into memory so they can be played without
+    /// delay, and report those that are missing or cannot be decoded.
+    pub(crate) fn preload(&mut self, names: &[String]) -> PreloadReport {
+        let mut report = PreloadReport::default();
+
+        for name in names {
+            if self.preloaded.contains_key(name) {
+                continue;
+            }
+
+            let path = match self.resolve(name) {
+                Ok(path) => path,
+                Err(_) => {
+                    report.missing.push(name.to_string());
+                    continue;
+                }
+            };
+
+            match decode_file(&path) {
+                Ok(buffer) => {
+                    self.preloaded.insert(name.to_string(), buffer);
+                    report.loaded += 1;
+                }
+                Err(e) => report
+                    .undecodable
+                    .push((path.display().to_string(), format!("{e:#}"))),
+            }
+        }
+
+        report
     }
 
-    fn load_sound(&self, filename: &str) -> Result<Decoder<BufReader<File>>> {
-        let path = self.path.join(filename);
+    /// Find the file for a sound name. Names may refer to subdirectories
+    /// of the sounds path (e.g. `welcome/alice`).
+    fn resolve(&self, name: &str) -> Result<PathBuf> {
+        let relative_path = Path::new(name);
         ensure!(
-            &path.exists(),
-            "Sound file {} does not exist.",
-            path.display()
+            relative_path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_))),
+            "Invalid sound name \"{}\"",
+            name
         );
 
-        let source = load_source(&path)?;
+        SOUND_FILE_EXTENSIONS
+            .iter()
+            .map(|extension| {
+                let mut filename = relative_path.as_os_str().to_owned();
+                filename.push(".");
+                filename.push(extension);
+                self.path.join(filename)
+            })
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No sound file found for \"{}\" in {} (supported extensions: {}).",
+                    name,
+                    self.path.display(),
+                    SOUND_FILE_EXTENSIONS.join(", ")
+                )
+            })
+    }
 
-        Ok(source)
+    pub(crate) fn is_preloaded(&self, name: &str) -> bool {
+        self.preloaded.contains_key(name)
     }
+
+    fn load_sound(&self, name: &str) -> Result<SamplesBuffer, SoundError> {
+        if let Some(buffer) = self.preloaded.get(name) {
+            return Ok(buffer.clone());
+        }
+
+        let path = self.resolve(name).map_err(SoundError::Missing)?;
+        decode_file(&path)
+            .with_context(|| format!("Could not decode sound file {}", path.display()))
+            .map_err(SoundError::Undecodable)
+    }
+}
+
+/// Why a sound could not be loaded
+enum SoundError {
+    /// No file exists for the sound (or its name is invalid).
+    Missing(anyhow::Error),
+    /// The sound file could not be decoded.
+    Undecodable(anyhow::Error),
 }
 
+/// Outcome of preloading sounds
+#[derive(Default)]
+pub(crate) struct PreloadReport {
+    pub loaded: usize,
+    /// Names of sounds without a file
+    pub missing: Vec<String>,
+    /// Files that could not be decoded, with the reason
+    pub undecodable: Vec<(String, String)>,
+}
+
+impl PreloadReport {
+    pub(crate) fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.undecodable.is_empty()
+    }
+
+    pub(crate) fn log(&self) {
+        log::info!("Preloaded {} sound(s).", self.loaded);
+        for name in &self.missing {
+            log::warn!("Sound \"{}\" is missing.", name);
+        }
+        for (path, reason) in &self.undecodable {
+            log::warn!("Sound file {} could not be decoded: {}", path, reason);
+        }
+    }
+}
+
+/// Priority of a sound. A sound interrupts a playing sound of lower
+/// priority and is played before queued sounds of lower priority.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum SoundPriority {
+    /// e.g. greetings
+    Low,
+    Normal,
+    /// e.g. error notifications
+    High,
+}
+
+/// Determines the volume at which sounds are played
+pub(crate) struct Volume {
+    master: f32,
+    sound_gains: HashMap<String, f32>,
+    quiet_hours: Option<QuietHours>,
+}
+
+impl Volume {
+    pub(crate) fn new(config: &AudioConfig) -> Result<Self> {
+        ensure!(
+            is_valid_volume(config.volume),
+            "Volume must be between 0 and {}",
+            MAX_VOLUME
+        );
+        for (name, gain) in &config.sound_gains {
+            ensure!(
+                is_valid_volume(*gain),
+                "Gain for sound \"{}\" must be between 0 and {}",
+                name,
+                MAX_VOLUME
+            );
+        }
+
+        let quiet_hours = match &config.quiet_hours {
+            Some(quiet_hours_config) => Some(QuietHours::new(quiet_hours_config)?),
+            None => None,
+        };
+
+        Ok(Self {
+            master: config.volume,
+            sound_gains: config.sound_gains.clone(),
+            quiet_hours,
+        })
+    }
+
+    fn active_quiet_hours(&self) -> Option<&QuietHours> {
+        self.quiet_hours
+            .as_ref()
+            .filter(|quiet_hours| quiet_hours.is_active_now())
+    }
+
+    fn for_sound(&self, name: Option<&str>, quiet_hours: Option<&QuietHours>) -> f32 {
+        let gain = name
+            .and_then(|name| self.sound_gains.get(name))
+            .copied()
+            .unwrap_or(1.0);
+        let quiet_hours_volume = quiet_hours.map_or(1.0, |quiet_hours| quiet_hours.volume);
+        self.master * gain * quiet_hours_volume
+    }
+}
+
+fn is_valid_volume(volume: f32) -> bool {
+    (0.0..=MAX_VOLUME).contains(&volume)
+}
+
+/// A sound to play, or a text to speak instead if the sound is not
+/// available (or none is given)
+struct QueuedSound {
+    name: Option<String>,
+    fallback_text: Option<String>,
+    priority: SoundPriority,
+    /// Speech synthesized from the fallback text
+    speech: Option<SamplesBuffer>,
+}
+
+enum AudioCommand {
+    Play(QueuedSound),
+    /// Speech has been synthesized (unless that failed) in the
+    /// background for the sound.
+    SpeechSynthesized {
+        sound: QueuedSound,
+        speech: Option<SamplesBuffer>,
+    },
+    WaitUntilFinished {
+        done_sender: Sender<()>,
+    },
+}
+
+/// Plays sounds on a background worker so that callers do not have to
+/// wait for playback to finish.
 pub(crate) struct AudioPlayer {
-    sound_lib: SoundLibrary,
-    _output_stream: OutputStream, // Hold reference to keep sound playback working!
-    sink: Sink,
+    command_sender: Sender<AudioCommand>,
 }
 
 impl AudioPlayer {
-    pub fn new(sounds_path: PathBuf) -> Result<AudioPlayer> {
-        let sound_lib = SoundLibrary::new(sounds_path);
+    pub fn new(
+        sound_lib: SoundLibrary,
+        synthesizer: Option<SpeechSynthesizer>,
+        volume: Volume,
+        health: Arc<Health>,
+    ) -> Result<AudioPlayer> {
+        let (command_sender, command_receiver) = flume::unbounded();
+        let (ready_sender, ready_receiver) = flume::bounded(1);
 
-        let output_stream = OutputStreamBuilder::open_default_stream()?;
-        let sink = Sink::connect_new(output_stream.mixer());
+        // Let the worker feed synthesized speech back to itself without
+        // keeping the channel open once the player is gone.
+        let synthesis_sender = command_sender.downgrade();
 
-        Ok(AudioPlayer {
-            sound_lib,
-            _output_stream: output_stream,
-            sink,
-        })
+        // The output stream cannot be moved between threads, so open it
+        // on the worker thread.
+        thread::spawn(move || {
+            let output_stream = match open_output_stream(health) {
+                Ok(output_stream) => output_stream,
+                Err(e) => {
+                    let _ = ready_sender.send(Err(anyhow!(e)));
+                    return;
+                }
+            };
+            let _ = ready_sender.send(Ok(()));
+
+            let worker = AudioWorker {
+                sound_lib,
+                synthesizer: synthesizer.map(Arc::new),
+                synthesis_sender,
+                pending_syntheses: 0,
+                volume,
+                sink: Sink::connect_new(output_stream.mixer()),
+                queue: VecDeque::new(),
+                current_priority: None,
+                waiters: Vec::new(),
+            };
+            worker.run(command_receiver);
+        });
+
+        ready_receiver
+            .recv()
+            .context("Audio worker terminated unexpectedly")??;
+
+        Ok(AudioPlayer { command_sender })
+    }
+
+    /// Create a player without audio output that reports the name of
+    /// each sound (or the text to speak) it is asked to play.
+    #[cfg(test)]
+    pub(crate) fn recording() -> (AudioPlayer, Receiver<String>) {
+        let (command_sender, command_receiver) = flume::unbounded();
+        let (played_sender, played_receiver) = flume::unbounded();
+
+        thread::spawn(move || {
+            for command in command_receiver.iter() {
+                match command {
+                    AudioCommand::Play(sound) | AudioCommand::SpeechSynthesized { sound, .. } => {
+                        if let Some(name) = sound.name.or(sound.fallback_text) {
+                            let _ = played_sender.send(name);
+                        }
+                    }
+                    AudioCommand::WaitUntilFinished { done_sender } => {
+                        let _ = done_sender.send(());
+                    }
+                }
+            }
+        });
+
+        (AudioPlayer { command_sender }, played_receiver)
     }
 
-    pub fn play(&self, name: &str) -> Result<()> {
-        let filename = format!("{}.ogg", name);
-        let source = self.sound_lib.load_sound(&filename)?;
-        self.sink.append(source);
-        self.sink.sleep_until_end();
+    /// Queue the sound for playback (interrupting a playing sound of
+    /// lower priority). If the sound is not available, the fallback text
+    /// is spoken (if text-to-speech is configured).
+    pub fn play(
+        &self,
+        name: &str,
+        fallback_text: Option<String>,
+        priority: SoundPriority,
+    ) -> Result<()> {
+        self.command_sender.send(AudioCommand::Play(QueuedSound {
+            name: Some(name.to_string()),
+            fallback_text,
+            priority,
+            speech: None,
+        }))?;
+        Ok(())
+    }
+
+    /// Queue the text to be spoken (if text-to-speech is configured).
+    pub fn speak(&self, text: String, priority: SoundPriority) -> Result<()> {
+        self.command_sender.send(AudioCommand::Play(QueuedSound {
+            name: None,
+            fallback_text: Some(text),
+            priority,
+            speech: None,
+        }))?;
+        Ok(())
+    }
 
+    /// Block until all queued sounds have been played.
+    pub fn wait_until_finished(&self) -> Result<()> {
+        let (done_sender, done_receiver) = flume::bounded(1);
+        self.command_sender
+            .send(AudioCommand::WaitUntilFinished { done_sender })?;
+        done_receiver.recv()?;
         Ok(())
     }
 }
 
-fn load_source(path: &Path) -> Result<Decoder<BufReader<File>>> {
+struct AudioWorker {
+    sound_lib: SoundLibrary,
+    synthesizer: Option<Arc<SpeechSynthesizer>>,
+    synthesis_sender: WeakSender<AudioCommand>,
+    /// Number of texts being synthesized in the background
+    pending_syntheses: usize,
+    volume: Volume,
+    sink: Sink,
+    queue: VecDeque<QueuedSound>,
+    /// Priority of the sound currently playing (if any)
+    current_priority: Option<SoundPriority>,
+    waiters: Vec<Sender<()>>,
+}
+
+impl AudioWorker {
+    fn run(mut self, command_receiver: Receiver<AudioCommand>) {
+        loop {
+            if self.sink.empty() {
+                self.current_priority = None;
+                self.play_next();
+            }
+
+            // A sound is playing (or an interrupted one is fading out).
+            let is_playing = !self.sink.empty();
+
+            if !is_playing && self.pending_syntheses == 0 {
+                for waiter in self.waiters.drain(..) {
+                    let _ = waiter.send(());
+                }
+            }
+
+            let command = if is_playing {
+                match command_receiver.recv_timeout(PLAYBACK_POLL_INTERVAL) {
+                    Ok(command) => command,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match command_receiver.recv() {
+                    Ok(command) => command,
+                    Err(_) => break,
+                }
+            };
+
+            match command {
+                AudioCommand::Play(sound) => self.enqueue(sound),
+                AudioCommand::SpeechSynthesized { mut sound, speech } => {
+                    self.pending_syntheses -= 1;
+                    if speech.is_some() {
+                        sound.speech = speech;
+                        self.enqueue(sound);
+                    }
+                }
+                AudioCommand::WaitUntilFinished { done_sender } => self.waiters.push(done_sender),
+            }
+        }
+    }
+
+    fn enqueue(&mut self, sound: QueuedSound) {
+        let priority = sound.priority;
+
+        if let Some(current_priority) = self.current_priority {
+            if priority > current_priority {
+                log::debug!("Interrupting sound for more important one.");
+                self.sink.stop();
+                self.current_priority = None;
+            }
+        }
+
+        // Keep the queue ordered by priority, first come first served
+        // within the same priority.
+        let index = self
+            .queue
+            .iter()
+            .position(|queued| queued.priority < priority)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(index, sound);
+    }
+
+    /// Start playing the next queued sound that can be loaded.
+    fn play_next(&mut self) {
+        while let Some(mut sound) = self.queue.pop_front() {
+            let quiet_hours = self.volume.active_quiet_hours();
+            self.sink
+                .set_volume(self.volume.for_sound(sound.name.as_deref(), quiet_hours));
+
+            if quiet_hours.is_some_and(|quiet_hours| quiet_hours.beep) {
+                self.sink
+                    .append(SineWave::new(BEEP_FREQUENCY).take_duration(BEEP_DURATION));
+                self.current_priority = Some(sound.priority);
+                return;
+            }
+
+            if let Some(buffer) = sound.speech.take().or_else(|| self.load(&sound)) {
+                self.sink.append(buffer);
+                self.current_priority = Some(sound.priority);
+                return;
+            }
+
+            self.synthesize_in_background(sound);
+        }
+    }
+
+    /// Load the sound. Problems are logged, but do not affect the audio
+    /// health (which only reflects the output).
+    fn load(&self, sound: &QueuedSound) -> Option<SamplesBuffer> {
+        let name = sound.name.as_ref()?;
+        match self.sound_lib.load_sound(name) {
+            Ok(buffer) => Some(buffer),
+            Err(SoundError::Missing(e)) => {
+                log::warn!("Sound is missing: {e:#}");
+                None
+            }
+            Err(SoundError::Undecodable(e)) => {
+                log::warn!("Sound is unplayable: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Synthesize the sound's fallback text (if any) without blocking
+    /// the playback of other sounds. The sound is queued again once its
+    /// speech is available.
+    fn synthesize_in_background(&mut self, sound: QueuedSound) {
+        let Some(text) = sound.fallback_text.clone() else {
+            return;
+        };
+        let Some(synthesizer) = self.synthesizer.clone() else {
+            if sound.name.is_none() {
+                log::warn!("Cannot speak text, text-to-speech is not configured.");
+            }
+            return;
+        };
+
+        if sound.name.is_some() {
+            log::info!("Speaking fallback text instead.");
+        }
+
+        let sender = self.synthesis_sender.clone();
+        self.pending_syntheses += 1;
+        thread::spawn(move || {
+            let speech = match synthesizer.synthesize(&text) {
+                Ok(buffer) => Some(buffer),
+                Err(e) => {
+                    log::warn!("Could not speak text: {e:#}");
+                    None
+                }
+            };
+
+            if let Some(sender) = sender.upgrade() {
+                let _ = sender.send(AudioCommand::SpeechSynthesized { sound, speech });
+            }
+        });
+    }
+}
+
+/// Open the default output, marking the audio health as failed on
+/// errors of the stream.
+fn open_output_stream(health: Arc<Health>) -> Result<OutputStream> {
+    let output_stream = OutputStreamBuilder::from_default_device()?
+        .with_error_callback(move |e| {
+            log::error!("Audio output failed: {e}");
+            health.set_audio_ok(false);
+        })
+        .open_stream_or_fallback()?;
+    Ok(output_stream)
+}
+
+/// Decode the sound file completely into memory.
+pub(crate) fn decode_file(path: &Path) -> Result<SamplesBuffer> {
     let file = BufReader::new(File::open(path)?);
-    Ok(Decoder::new(file)?)
+    let decoder = Decoder::new(file)?;
+
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+    let samples: Vec<f32> = decoder.collect();
+
+    Ok(SamplesBuffer::new(channels, sample_rate, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio_config(volume: f32, gain: f32) -> AudioConfig {
+        AudioConfig {
+            volume,
+            sound_gains: HashMap::from([("welcome".to_string(), gain)]),
+            quiet_hours: None,
+        }
+    }
+
+    #[test]
+    fn test_volume_and_gains_are_combined() {
+        let volume = Volume::new(&audio_config(0.5, 2.0)).unwrap();
+
+        assert_eq!(volume.for_sound(Some("welcome"), None), 1.0);
+        assert_eq!(volume.for_sound(Some("other"), None), 0.5);
+        assert_eq!(volume.for_sound(None, None), 0.5);
+    }
+
+    #[test]
+    fn test_volume_and_gains_are_limited() {
+        assert!(Volume::new(&audio_config(0.0, 0.0)).is_ok());
+        assert!(Volume::new(&audio_config(MAX_VOLUME, MAX_VOLUME)).is_ok());
+
+        for value in [-0.1, 2.1, 100.0, f32::NAN, f32::INFINITY] {
+            assert!(Volume::new(&audio_config(value, 1.0)).is_err(), "{value}");
+            assert!(Volume::new(&audio_config(1.0, value)).is_err(), "{value}");
+        }
+    }
+
+    #[cfg(feature = "ogg")]
+    #[test]
+    fn test_missing_and_undecodable_sounds_are_told_apart() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("broken.ogg"), b"not a sound").unwrap();
+        let sound_lib = SoundLibrary::new(directory.path().to_path_buf());
+
+        assert!(matches!(
+            sound_lib.load_sound("broken"),
+            Err(SoundError::Undecodable(_))
+        ));
+        assert!(matches!(
+            sound_lib.load_sound("absent"),
+            Err(SoundError::Missing(_))
+        ));
+        assert!(matches!(
+            sound_lib.load_sound("../broken"),
+            Err(SoundError::Missing(_))
+        ));
+    }
 }
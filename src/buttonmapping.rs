@@ -0,0 +1,103 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, ensure};
+use evdev::{Device, EventSummary, KeyCode};
+
+use crate::devices;
+use crate::keycodes;
+
+/// Interactively determine the key codes of the buttons and produce the
+/// `buttons_to_key_codes` configuration section.
+pub(crate) fn map_buttons(
+    device_name: String,
+    button_count: u8,
+    output_filename: Option<PathBuf>,
+) -> Result<()> {
+    // Refuse to overwrite an existing file (e.g. the configuration file),
+    // preferably before the buttons have been pressed.
+    if let Some(filename) = &output_filename {
+        ensure_output_file_does_not_exist(filename)?;
+    }
+
+    let mut device = devices::open_input_device(device_name, "button input device".to_string())?;
+
+    let mut key_codes: Vec<KeyCode> = Vec::new();
+    for number in 1..=button_count {
+        print!("Press button {number} ... ");
+        io::stdout().flush()?;
+
+        let key_code = wait_for_new_key_code(&mut device, &key_codes)?;
+        println!("{}", keycodes::get_key_code_name(key_code));
+
+        key_codes.push(key_code);
+    }
+
+    let section = format_section(&key_codes);
+
+    match output_filename {
+        Some(filename) => {
+            create_output_file(&filename)?
+                .write_all(section.as_bytes())
+                .with_context(|| format!("Could not write to {}", filename.display()))?;
+            println!("Wrote button mapping to {}.", filename.display());
+        }
+        None => {
+            println!();
+            print!("{section}");
+        }
+    }
+
+    Ok(())
+}
+
+fn ensure_output_file_does_not_exist(filename: &Path) -> Result<()> {
+    ensure!(
+        !filename.exists(),
+        "{} already exists. Specify a new file and copy the section into the configuration file yourself.",
+        filename.display()
+    );
+    Ok(())
+}
+
+fn create_output_file(filename: &Path) -> Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(filename)
+        .with_context(|| format!("Could not create {}", filename.display()))
+}
+
+/// Wait for the release of a key that has not been mapped yet.
+fn wait_for_new_key_code(device: &mut Device, mapped_key_codes: &[KeyCode]) -> Result<KeyCode> {
+    loop {
+        for event in device.fetch_events()? {
+            if let EventSummary::Key(_, key_code, 0) = event.destructure() {
+                if mapped_key_codes.contains(&key_code) {
+                    print!("(already mapped, press another button) ");
+                    io::stdout().flush()?;
+                    continue;
+                }
+                return Ok(key_code);
+            }
+        }
+    }
+}
+
+fn format_section(key_codes: &[KeyCode]) -> String {
+    let mut section = String::from("[buttons_to_key_codes]\n");
+    for (i, key_code) in key_codes.iter().enumerate() {
+        section.push_str(&format!(
+            "button{} = \"{}\"\n",
+            i + 1,
+            keycodes::get_key_code_name(*key_code)
+        ));
+    }
+    section
+}
@@ -4,26 +4,53 @@
  */
 
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::os::fd::AsFd;
 use std::thread;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result};
-use evdev::{Device, EventSummary, EventType, InputEvent, KeyCode};
+use evdev::{Device, EventSummary, InputEvent, KeyCode};
 use flume::Sender;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
 use serde::Deserialize;
 
-use crate::devices;
-use crate::events::Event;
+use crate::config::ButtonsConfig;
+use crate::devices::{self, ReconnectingInputDevice};
+use crate::events::{Event, InputDevice};
+use crate::keycodes;
 
 pub(crate) fn handle_button_presses(
     device_name: String,
     buttons_to_key_code_names: HashMap<Button, String>,
+    buttons_config: &ButtonsConfig,
     sender: Sender<Event>,
 ) -> Result<()> {
     let key_codes_to_buttons = map_key_codes_to_buttons(buttons_to_key_code_names)?;
 
-    let device = open_device(device_name)?;
+    let (reconnecting_device, device) = ReconnectingInputDevice::open(
+        device_name,
+        "button input device".to_string(),
+        InputDevice::Buttons,
+    )?;
 
-    let button_handler = ButtonHandler::new(key_codes_to_buttons, sender);
+    let press_detector = ButtonPressDetector::new(
+        Duration::from_millis(buttons_config.debounce_in_ms),
+        buttons_config
+            .long_press_threshold_in_ms
+            .map(Duration::from_millis),
+        buttons_config
+            .double_press_window_in_ms
+            .map(Duration::from_millis),
+    );
+
+    let button_handler = ButtonHandler::new(
+        key_codes_to_buttons,
+        press_detector,
+        reconnecting_device,
+        sender,
+    );
     thread::spawn(move || button_handler.run(device));
     Ok(())
 }
@@ -34,8 +61,8 @@ fn map_key_codes_to_buttons(
     let mut key_codes_to_buttons: HashMap<KeyCode, Button> = HashMap::new();
 
     for (button, key_code_name) in buttons_to_key_code_names {
-        let key_code = find_key_code_by_name(&key_code_name)
-            .with_context(|| format!("Unknown button key code name '{}'", key_code_name))?;
+        let key_code = keycodes::parse_key_code_name(&key_code_name)
+            .with_context(|| format!("Invalid key code for {}", button))?;
 
         key_codes_to_buttons.insert(key_code, button);
     }
@@ -43,50 +70,96 @@ fn map_key_codes_to_buttons(
     Ok(key_codes_to_buttons)
 }
 
-fn open_device(device_name: String) -> Result<Device> {
-    let device_label = "button input device".to_string();
-    devices::open_input_device(device_name, device_label)
+/// The kind of a button press
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ButtonPress {
+    Short,
+    Long,
+    Double,
 }
 
 struct ButtonHandler {
     key_codes_to_buttons: HashMap<KeyCode, Button>,
+    press_detector: ButtonPressDetector,
+    reconnecting_device: ReconnectingInputDevice,
     sender: Sender<Event>,
 }
 
 impl ButtonHandler {
-    fn new(key_codes_to_buttons: HashMap<KeyCode, Button>, sender: Sender<Event>) -> Self {
+    fn new(
+        key_codes_to_buttons: HashMap<KeyCode, Button>,
+        press_detector: ButtonPressDetector,
+        reconnecting_device: ReconnectingInputDevice,
+        sender: Sender<Event>,
+    ) -> Self {
         Self {
             key_codes_to_buttons,
+            press_detector,
+            reconnecting_device,
             sender,
         }
     }
 
-    fn run(&self, mut device: Device) -> Result<()> {
+    fn run(mut self, mut device: Device) -> Result<()> {
         loop {
-            for event in device.fetch_events()? {
-                if let Some(button) = self.handle_button_press(event) {
-                    let event = Event::ButtonPressed { button };
-                    self.sender.send(event)?;
-                }
-            }
+            let error = self.read_button_presses(&mut device)?;
+            device = self
+                .reconnecting_device
+                .reconnect(device, error, &self.sender)?;
         }
     }
 
-    fn handle_button_press(&self, event: InputEvent) -> Option<Button> {
-        if !self.is_key_released(event) {
-            return None;
+    /// Read button presses until the device fails, then return that
+    /// error.
+    fn read_button_presses(&mut self, device: &mut Device) -> Result<io::Error> {
+        let mut presses = Vec::new();
+        loop {
+            let timeout = self.press_detector.next_deadline().map(|deadline| {
+                deadline
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+            });
+
+            match wait_for_events(device, timeout) {
+                Ok(true) => match device.fetch_events() {
+                    Ok(events) => {
+                        for event in events {
+                            self.handle_button_event(event, &mut presses);
+                        }
+                    }
+                    Err(e) if devices::is_interruption(&e) => {}
+                    Err(e) => return Ok(e),
+                },
+                Ok(false) => {}
+                Err(e) if devices::is_interruption(&e) => {}
+                Err(e) => return Ok(e),
+            }
+
+            self.press_detector
+                .expire_pending(SystemTime::now(), &mut presses);
+
+            for (button, press) in presses.drain(..) {
+                let event = Event::ButtonPressed { button, press };
+                self.sender.send(event)?;
+            }
         }
+    }
 
-        let key_code = match event.destructure() {
-            EventSummary::Key(_, key_code, _) => Some(key_code),
-            _ => None,
+    fn handle_button_event(&mut self, event: InputEvent, presses: &mut Vec<(Button, ButtonPress)>) {
+        let EventSummary::Key(_, key_code, value) = event.destructure() else {
+            return;
         };
 
-        key_code.and_then(|kc| self.find_button_for_key_code(kc))
-    }
+        let pressed = match value {
+            0 => false,
+            1 => true,
+            _ => return, // Ignore auto-repeat.
+        };
 
-    fn is_key_released(&self, event: InputEvent) -> bool {
-        event.event_type() == EventType::KEY && event.value() == 0
+        if let Some(button) = self.find_button_for_key_code(key_code) {
+            self.press_detector
+                .handle(button, pressed, event.timestamp(), presses);
+        }
     }
 
     fn find_button_for_key_code(&self, key_code: KeyCode) -> Option<Button> {
@@ -94,82 +167,436 @@ impl ButtonHandler {
     }
 }
 
-fn find_key_code_by_name(name: &str) -> Option<KeyCode> {
-    match name {
-        // mouse
-        "left" => Some(KeyCode::BTN_LEFT),
-        "right" => Some(KeyCode::BTN_RIGHT),
-        "middle" => Some(KeyCode::BTN_MIDDLE),
-        "side" => Some(KeyCode::BTN_SIDE),
-        "extra" => Some(KeyCode::BTN_EXTRA),
-        "forward" => Some(KeyCode::BTN_FORWARD),
-        "back" => Some(KeyCode::BTN_BACK),
-        "task" => Some(KeyCode::BTN_TASK),
-
-        // joystick
-        "trigger" => Some(KeyCode::BTN_TRIGGER),
-        "thumb" => Some(KeyCode::BTN_THUMB),
-        "thumb2" => Some(KeyCode::BTN_THUMB2),
-        "top" => Some(KeyCode::BTN_TOP),
-        "top2" => Some(KeyCode::BTN_TOP2),
-        "pinkie" => Some(KeyCode::BTN_PINKIE),
-        "base" => Some(KeyCode::BTN_BASE),
-        "base2" => Some(KeyCode::BTN_BASE2),
-        "base3" => Some(KeyCode::BTN_BASE3),
-        "base4" => Some(KeyCode::BTN_BASE4),
-        "base5" => Some(KeyCode::BTN_BASE5),
-        "base6" => Some(KeyCode::BTN_BASE6),
-        "dead" => Some(KeyCode::BTN_DEAD),
-
-        // gamepad
-        "south" => Some(KeyCode::BTN_SOUTH),
-        "a" => Some(KeyCode::BTN_SOUTH),
-        "east" => Some(KeyCode::BTN_EAST),
-        "b" => Some(KeyCode::BTN_EAST),
-        "c" => Some(KeyCode::BTN_C),
-        "north" => Some(KeyCode::BTN_NORTH),
-        "x" => Some(KeyCode::BTN_NORTH),
-        "west" => Some(KeyCode::BTN_WEST),
-        "y" => Some(KeyCode::BTN_WEST),
-        "z" => Some(KeyCode::BTN_Z),
-        "tl" => Some(KeyCode::BTN_TL),
-        "tr" => Some(KeyCode::BTN_TR),
-        "tl2" => Some(KeyCode::BTN_TL2),
-        "tr2" => Some(KeyCode::BTN_TR2),
-        "select" => Some(KeyCode::BTN_SELECT),
-        "start" => Some(KeyCode::BTN_START),
-        "mode" => Some(KeyCode::BTN_MODE),
-        "thumbl" => Some(KeyCode::BTN_THUMBL),
-        "thumbr" => Some(KeyCode::BTN_THUMBR),
-
-        // directional pad
-        "dpad_up" => Some(KeyCode::BTN_DPAD_UP),
-        "dpad_down" => Some(KeyCode::BTN_DPAD_DOWN),
-        "dpad_left" => Some(KeyCode::BTN_DPAD_LEFT),
-        "dpad_right" => Some(KeyCode::BTN_DPAD_RIGHT),
-
-        "trigger_happy1" => Some(KeyCode::BTN_TRIGGER_HAPPY1),
-        "trigger_happy2" => Some(KeyCode::BTN_TRIGGER_HAPPY2),
-        "trigger_happy3" => Some(KeyCode::BTN_TRIGGER_HAPPY3),
-        "trigger_happy4" => Some(KeyCode::BTN_TRIGGER_HAPPY4),
-        "trigger_happy5" => Some(KeyCode::BTN_TRIGGER_HAPPY5),
-        "trigger_happy6" => Some(KeyCode::BTN_TRIGGER_HAPPY6),
-        "trigger_happy7" => Some(KeyCode::BTN_TRIGGER_HAPPY7),
-        "trigger_happy8" => Some(KeyCode::BTN_TRIGGER_HAPPY8),
-
-        _ => None,
+/// Wait until the device has events to read or the timeout (if any) has
+/// elapsed. Return whether events are available.
+fn wait_for_events(device: &Device, timeout: Option<Duration>) -> io::Result<bool> {
+    let timeout = match timeout {
+        Some(timeout) => PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX),
+        None => PollTimeout::NONE,
+    };
+
+    let mut poll_fds = [PollFd::new(device.as_fd(), PollFlags::POLLIN)];
+    let ready = poll(&mut poll_fds, timeout)?;
+    Ok(ready > 0)
+}
+
+/// Derives debounced short, long, and double presses from the press
+/// and release events of buttons.
+struct ButtonPressDetector {
+    debounce: Duration,
+    long_press_threshold: Option<Duration>,
+    double_press_window: Option<Duration>,
+    states: HashMap<Button, ButtonState>,
+}
+
+#[derive(Default)]
+struct ButtonState {
+    /// Debounced state: time the button was pressed, if it is held down
+    pressed_at: Option<SystemTime>,
+    last_transition_at: Option<SystemTime>,
+    /// State last reported by the device (possibly still bouncing), and
+    /// when it was reported
+    raw_pressed: bool,
+    raw_changed_at: Option<SystemTime>,
+    /// Time of release of a short press that might turn out to be the
+    /// first half of a double press
+    pending_since: Option<SystemTime>,
+}
+
+impl ButtonState {
+    fn is_settled(&self) -> bool {
+        self.raw_pressed == self.pressed_at.is_some()
+    }
+}
+
+impl ButtonPressDetector {
+    fn new(
+        debounce: Duration,
+        long_press_threshold: Option<Duration>,
+        double_press_window: Option<Duration>,
+    ) -> Self {
+        Self {
+            debounce,
+            long_press_threshold,
+            double_press_window,
+            states: HashMap::new(),
+        }
+    }
+
+    fn handle(
+        &mut self,
+        button: Button,
+        pressed: bool,
+        timestamp: SystemTime,
+        presses: &mut Vec<(Button, ButtonPress)>,
+    ) {
+        let state = self.states.entry(button.clone()).or_default();
+        state.raw_pressed = pressed;
+        state.raw_changed_at = Some(timestamp);
+
+        self.settle(&button, timestamp, presses);
+    }
+
+    /// Take over the state reported by the device, unless the last
+    /// transition happened less than the debounce time ago (contact
+    /// bounce). In that case, the state is settled later on (see
+    /// `expire_pending`) so that the final state is not lost.
+    fn settle(
+        &mut self,
+        button: &Button,
+        now: SystemTime,
+        presses: &mut Vec<(Button, ButtonPress)>,
+    ) {
+        let Some(state) = self.states.get(button) else {
+            return;
+        };
+
+        if state.is_settled() {
+            return;
+        }
+
+        if let Some(last_transition_at) = state.last_transition_at {
+            if elapsed(last_transition_at, now) < self.debounce {
+                return;
+            }
+        }
+
+        let pressed = state.raw_pressed;
+        let timestamp = state.raw_changed_at.unwrap_or(now);
+        self.apply_transition(button, pressed, timestamp, presses);
+    }
+
+    fn apply_transition(
+        &mut self,
+        button: &Button,
+        pressed: bool,
+        timestamp: SystemTime,
+        presses: &mut Vec<(Button, ButtonPress)>,
+    ) {
+        let long_press_threshold = self.long_press_threshold;
+        let double_press_window = self.double_press_window;
+        let Some(state) = self.states.get_mut(button) else {
+            return;
+        };
+        let button = button.clone();
+
+        state.last_transition_at = Some(timestamp);
+
+        if pressed {
+            state.pressed_at = Some(timestamp);
+            return;
+        }
+
+        let Some(pressed_at) = state.pressed_at.take() else {
+            return;
+        };
+
+        if let Some(threshold) = long_press_threshold {
+            if elapsed(pressed_at, timestamp) >= threshold {
+                presses.push((button, ButtonPress::Long));
+                return;
+            }
+        }
+
+        let Some(window) = double_press_window else {
+            presses.push((button, ButtonPress::Short));
+            return;
+        };
+
+        match state.pending_since.take() {
+            Some(pending_since) if elapsed(pending_since, timestamp) <= window => {
+                presses.push((button, ButtonPress::Double));
+            }
+            Some(_) => {
+                // The earlier press was not followed up in time.
+                presses.push((button, ButtonPress::Short));
+                state.pending_since = Some(timestamp);
+            }
+            None => {
+                state.pending_since = Some(timestamp);
+            }
+        }
+    }
+
+    /// Return when bouncing buttons have to be settled or pending short
+    /// presses expire, whichever comes first.
+    fn next_deadline(&self) -> Option<SystemTime> {
+        self.states
+            .values()
+            .flat_map(|state| {
+                let settle_deadline = state
+                    .last_transition_at
+                    .filter(|_| !state.is_settled())
+                    .map(|last_transition_at| last_transition_at + self.debounce);
+                let pending_deadline = state
+                    .pending_since
+                    .zip(self.double_press_window)
+                    .map(|(pending_since, window)| pending_since + window);
+                [settle_deadline, pending_deadline]
+            })
+            .flatten()
+            .min()
+    }
+
+    /// Settle buttons that have stopped bouncing, and report short
+    /// presses that have not been followed up by a second press within
+    /// the double press window.
+    fn expire_pending(&mut self, now: SystemTime, presses: &mut Vec<(Button, ButtonPress)>) {
+        let unsettled_buttons: Vec<Button> = self
+            .states
+            .iter()
+            .filter(|(_, state)| !state.is_settled())
+            .map(|(button, _)| button.clone())
+            .collect();
+        for button in unsettled_buttons {
+            self.settle(&button, now, presses);
+        }
+
+        let Some(window) = self.double_press_window else {
+            return;
+        };
+
+        for (button, state) in self.states.iter_mut() {
+            if let Some(pending_since) = state.pending_since {
+                if elapsed(pending_since, now) > window {
+                    state.pending_since = None;
+                    presses.push((button.clone(), ButtonPress::Short));
+                }
+            }
+        }
     }
 }
 
+fn elapsed(earlier: SystemTime, later: SystemTime) -> Duration {
+    later.duration_since(earlier).unwrap_or_default()
+}
+
+/// A button, identified by its number (e.g. `button1`, `button12`)
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub(crate) enum Button {
-    Button1,
-    Button2,
-    Button3,
-    Button4,
-    Button5,
-    Button6,
-    Button7,
-    Button8,
+#[serde(try_from = "String")]
+pub(crate) struct Button(u8);
+
+impl TryFrom<String> for Button {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        // Accept only plain digits without leading zero so that each
+        // button has exactly one name.
+        name.strip_prefix("button")
+            .filter(|number| number.bytes().all(|b| b.is_ascii_digit()))
+            .filter(|number| !number.starts_with('0'))
+            .and_then(|number| number.parse::<u8>().ok())
+            .map(Button)
+            .ok_or_else(|| format!("Invalid button name '{}', expected e.g. 'button1'", name))
+    }
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "button{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE: Duration = Duration::from_millis(30);
+    const LONG_PRESS_THRESHOLD: Duration = Duration::from_millis(800);
+    const DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(300);
+
+    fn button(number: u8) -> Button {
+        Button(number)
+    }
+
+    fn at(milliseconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(milliseconds)
+    }
+
+    /// Feed (button number, pressed, milliseconds) transitions, then
+    /// expire pending presses at the given time.
+    fn detect(
+        detector: &mut ButtonPressDetector,
+        transitions: &[(u8, bool, u64)],
+        expire_at: u64,
+    ) -> Vec<(Button, ButtonPress)> {
+        let mut presses = Vec::new();
+        for (number, pressed, milliseconds) in transitions {
+            detector.handle(button(*number), *pressed, at(*milliseconds), &mut presses);
+        }
+        detector.expire_pending(at(expire_at), &mut presses);
+        presses
+    }
+
+    fn simple_detector() -> ButtonPressDetector {
+        ButtonPressDetector::new(DEBOUNCE, None, None)
+    }
+
+    fn full_detector() -> ButtonPressDetector {
+        ButtonPressDetector::new(
+            DEBOUNCE,
+            Some(LONG_PRESS_THRESHOLD),
+            Some(DOUBLE_PRESS_WINDOW),
+        )
+    }
+
+    #[test]
+    fn test_short_press() {
+        let presses = detect(
+            &mut simple_detector(),
+            &[(1, true, 0), (1, false, 100)],
+            100,
+        );
+        assert_eq!(presses, [(button(1), ButtonPress::Short)]);
+    }
+
+    #[test]
+    fn test_long_press() {
+        let presses = detect(&mut full_detector(), &[(1, true, 0), (1, false, 900)], 900);
+        assert_eq!(presses, [(button(1), ButtonPress::Long)]);
+    }
+
+    #[test]
+    fn test_press_below_long_press_threshold_is_short() {
+        let presses = detect(&mut full_detector(), &[(1, true, 0), (1, false, 700)], 1100);
+        assert_eq!(presses, [(button(1), ButtonPress::Short)]);
+    }
+
+    #[test]
+    fn test_double_press() {
+        let presses = detect(
+            &mut full_detector(),
+            &[
+                (1, true, 0),
+                (1, false, 100),
+                (1, true, 200),
+                (1, false, 300),
+            ],
+            1000,
+        );
+        assert_eq!(presses, [(button(1), ButtonPress::Double)]);
+    }
+
+    #[test]
+    fn test_presses_too_far_apart_are_not_double() {
+        let presses = detect(
+            &mut full_detector(),
+            &[
+                (1, true, 0),
+                (1, false, 100),
+                (1, true, 500),
+                (1, false, 600),
+            ],
+            1000,
+        );
+        assert_eq!(
+            presses,
+            [
+                (button(1), ButtonPress::Short),
+                (button(1), ButtonPress::Short)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expire_pending_reports_short_press_after_window() {
+        let mut detector = full_detector();
+        let mut presses = Vec::new();
+        detector.handle(button(1), true, at(0), &mut presses);
+        detector.handle(button(1), false, at(100), &mut presses);
+        assert!(presses.is_empty());
+        assert_eq!(detector.next_deadline(), Some(at(400)));
+
+        detector.expire_pending(at(400), &mut presses);
+        assert!(presses.is_empty());
+
+        detector.expire_pending(at(401), &mut presses);
+        assert_eq!(presses, [(button(1), ButtonPress::Short)]);
+        assert_eq!(detector.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_bounce_is_suppressed() {
+        let presses = detect(
+            &mut simple_detector(),
+            &[
+                (1, true, 0),
+                (1, false, 5),
+                (1, true, 8),
+                (1, false, 100),
+                (1, true, 104),
+                (1, false, 110),
+            ],
+            200,
+        );
+        assert_eq!(presses, [(button(1), ButtonPress::Short)]);
+    }
+
+    #[test]
+    fn test_release_during_bounce_is_settled_later() {
+        let mut detector = simple_detector();
+        let mut presses = Vec::new();
+
+        // The last transition of the bounce is the release.
+        detector.handle(button(1), true, at(0), &mut presses);
+        detector.handle(button(1), false, at(10), &mut presses);
+        assert!(presses.is_empty());
+        assert_eq!(detector.next_deadline(), Some(at(30)));
+
+        detector.expire_pending(at(30), &mut presses);
+        assert_eq!(presses, [(button(1), ButtonPress::Short)]);
+        assert_eq!(detector.next_deadline(), None);
+
+        // The next real press is detected as such.
+        presses.clear();
+        detector.handle(button(1), true, at(500), &mut presses);
+        detector.handle(button(1), false, at(600), &mut presses);
+        assert_eq!(presses, [(button(1), ButtonPress::Short)]);
+    }
+
+    #[test]
+    fn test_buttons_are_independent() {
+        let presses = detect(
+            &mut simple_detector(),
+            &[(1, true, 0), (2, true, 5), (2, false, 100), (1, false, 110)],
+            200,
+        );
+        assert_eq!(
+            presses,
+            [
+                (button(2), ButtonPress::Short),
+                (button(1), ButtonPress::Short)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_button_name() {
+        assert_eq!(Button::try_from("button1".to_string()), Ok(button(1)));
+        assert_eq!(Button::try_from("button12".to_string()), Ok(button(12)));
+        assert_eq!(Button::try_from("button255".to_string()), Ok(button(255)));
+    }
+
+    #[test]
+    fn test_parse_button_name_rejects_ambiguous_or_invalid_names() {
+        for name in [
+            "button",
+            "button0",
+            "button012",
+            "button+5",
+            "button-1",
+            "button 1",
+            "button256",
+            "Button1",
+            "key1",
+        ] {
+            assert!(Button::try_from(name.to_string()).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_release_without_press_is_ignored() {
+        let presses = detect(&mut simple_detector(), &[(1, false, 100)], 200);
+        assert!(presses.is_empty());
+    }
 }
@@ -36,11 +36,59 @@ pub(crate) enum Command {
         disable_tls_verification: bool,
     },
 
+    /// List input devices that might be tag readers or button devices
+    Devices {
+        /// Print a configuration snippet for the detected devices
+        #[clap(long = "config-snippet")]
+        config_snippet: bool,
+    },
+
+    /// Interactively map buttons to key codes
+    MapButtons {
+        /// Specify button input device (e.g. `/dev/input/event42`)
+        #[clap(long = "device")]
+        device: String,
+
+        /// Supply number of buttons
+        #[clap(long = "button-count")]
+        button_count: u8,
+
+        /// Write configuration section to a new file (which must not exist
+        /// yet) instead of standard output
+        #[clap(short = 'o', long = "output")]
+        output_filename: Option<PathBuf>,
+    },
+
+    /// Create virtual input devices for the configured reader and buttons
+    /// and feed them with commands from standard input
+    EmulateDevices {
+        /// Specify configuration filename (e.g. `config.toml`)
+        #[clap(short = 'c', long = "config")]
+        config_filename: PathBuf,
+    },
+
+    /// Serve a mock of the whereabouts API for testing
+    MockApi {
+        /// Specify address to listen on
+        #[clap(long = "listen", default_value = "127.0.0.1:8080")]
+        listen_address: String,
+
+        /// Specify scenario filename with responses and failures (e.g.
+        /// `scenario.toml`)
+        #[clap(long = "scenario")]
+        scenario_filename: Option<PathBuf>,
+    },
+
     /// Run the Verbleiber client
     Run {
         /// Specify configuration filename (e.g. `config.toml`)
         #[clap(short = 'c', long = "config")]
         config_filename: PathBuf,
+
+        /// Read tag reads and button presses from a script file (or, if
+        /// none is given, from standard input) instead of input devices
+        #[clap(long = "simulate", value_name = "SCRIPT")]
+        simulate: Option<Option<PathBuf>>,
     },
 }
 
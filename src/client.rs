@@ -3,39 +3,132 @@
  * License: MIT
  */
 
+use std::cell::{Cell, RefCell};
 use std::path::PathBuf;
-
-use anyhow::Result;
-use flume::Receiver;
-
-use crate::api::ApiClient;
-use crate::audio::AudioPlayer;
-use crate::buttons::Button;
-use crate::config::{ApiConfig, PartyConfig};
-use crate::events::Event;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, ensure};
+use flume::{Receiver, RecvTimeoutError};
+
+use crate::api::{ApiClient, ApiError, StatusUpdate, TagDetails, TagUser};
+use crate::audio::{AudioPlayer, SoundLibrary, SoundPriority, Volume};
+use crate::buttons::{Button, ButtonPress};
+use crate::config::{Config, PartyConfig, TtsConfig};
+use crate::events::{Event, InputDevice};
+use crate::heartbeat::{self, Health};
 use crate::model::{UserId, UserMode};
+use crate::queue::{self, StatusQueueHandle};
 use crate::random::Random;
+use crate::tagcache::TagCache;
+use crate::tts::{self, SpeechSynthesizer};
+
+/// Sounds played by the client itself (as opposed to user- and
+/// whereabouts-specific sounds) regardless of configuration
+const BUILTIN_SOUND_NAMES: &[&str] = &[
+    "signon_successful",
+    "signon_failed",
+    "signoff_successful",
+    "signoff_failed",
+    "status_changed",
+    "communication_failed",
+];
+
+/// Played if the client token was rejected, or else
+/// `communication_failed`, so that it is not required
+const CLIENT_NOT_AUTHORIZED_SOUND_NAME: &str = "client_not_authorized";
 
 struct Client {
     audio_player: AudioPlayer,
     random: Random,
     api_client: ApiClient,
+    status_queue: Option<StatusQueueHandle>,
+    tag_cache: Option<RefCell<TagCache>>,
+    /// Disabled if the API does not offer the bulk endpoint
+    prefetch_tag_details: Cell<bool>,
     party_config: PartyConfig,
+    tts_config: Option<TtsConfig>,
+    health: Arc<Health>,
+    /// Whether the sound for a rejected client token is available
+    has_client_not_authorized_sound: bool,
     event_receiver: Receiver<Event>,
 }
 
 impl Client {
     fn new(
-        sounds_path: PathBuf,
-        api_config: &ApiConfig,
-        party_config: PartyConfig,
+        config: Config,
+        sound_lib: SoundLibrary,
+        event_receiver: Receiver<Event>,
+        open_devices: &[InputDevice],
+    ) -> Result<Self> {
+        let health = Arc::new(Health::new(open_devices));
+
+        let synthesizer = match &config.tts {
+            Some(tts_config) => Some(SpeechSynthesizer::new(tts_config.command.clone())?),
+            None => None,
+        };
+        let volume = Volume::new(&config.audio)?;
+        let has_client_not_authorized_sound =
+            sound_lib.is_preloaded(CLIENT_NOT_AUTHORIZED_SOUND_NAME);
+        let audio_player = AudioPlayer::new(sound_lib, synthesizer, volume, health.clone())?;
+
+        Self::with_audio_player(
+            config,
+            audio_player,
+            has_client_not_authorized_sound,
+            health,
+            event_receiver,
+        )
+    }
+
+    fn with_audio_player(
+        config: Config,
+        audio_player: AudioPlayer,
+        has_client_not_authorized_sound: bool,
+        health: Arc<Health>,
         event_receiver: Receiver<Event>,
     ) -> Result<Self> {
+        let api_config = &config.api;
+        let party_id = config.party.party_id.clone();
+
+        let status_queue = match config.status_queue {
+            Some(config) => Some(queue::start_status_queue(
+                config.path,
+                Duration::from_secs(config.retry_interval_in_seconds),
+                ApiClient::new(api_config, party_id.clone()),
+            )?),
+            None => None,
+        };
+
+        let prefetch_tag_details = config.tag_cache.as_ref().is_some_and(|c| c.prefetch);
+        let tag_cache = match config.tag_cache {
+            Some(config) => Some(RefCell::new(TagCache::load(
+                config.path,
+                Duration::from_secs(config.ttl_in_seconds),
+            )?)),
+            None => None,
+        };
+
+        if let Some(heartbeat_config) = config.heartbeat {
+            heartbeat::start_heartbeat(
+                heartbeat_config,
+                ApiClient::new(api_config, party_id.clone()),
+                health.clone(),
+                status_queue.clone(),
+            );
+        }
+
         Ok(Self {
-            audio_player: AudioPlayer::new(sounds_path)?,
+            audio_player,
             random: Random::new(),
-            api_client: ApiClient::new(api_config, party_config.party_id.clone()),
-            party_config,
+            api_client: ApiClient::new(api_config, party_id),
+            status_queue,
+            tag_cache,
+            prefetch_tag_details: Cell::new(prefetch_tag_details),
+            party_config: config.party,
+            tts_config: config.tts,
+            health,
+            has_client_not_authorized_sound,
             event_receiver,
         })
     }
@@ -46,15 +139,50 @@ impl Client {
             Ok(()) => {
                 log::info!("Signed on.");
                 self.play_sound("signon_successful");
+
+                if self.prefetch_tag_details.get() {
+                    self.prefetch_tag_details();
+                }
             }
             Err(e) => {
                 log::warn!("Signing on failed.\n{e}");
-                self.play_sound("signon_failed");
+                self.play_api_error_sound(&e, "signon_failed");
             }
         }
         Ok(())
     }
 
+    fn prefetch_tag_details(&self) {
+        let Some(tag_cache) = &self.tag_cache else {
+            return;
+        };
+
+        log::info!("Prefetching tag details ...");
+        let all_details = match self.api_client.get_all_tag_details() {
+            Ok(all_details) => all_details,
+            Err(ApiError::NotFound(_)) => {
+                log::warn!(
+                    "The API does not offer tag details in bulk (`GET /tags`), disabling prefetching."
+                );
+                self.prefetch_tag_details.set(false);
+                return;
+            }
+            Err(e) => {
+                log::warn!("Prefetching tag details failed.\n{e}");
+                return;
+            }
+        };
+
+        let result = tag_cache.borrow_mut().insert_all(all_details);
+        match result {
+            Ok(()) => log::info!(
+                "Prefetched tag details. {} tags cached.",
+                tag_cache.borrow().len()
+            ),
+            Err(e) => log::warn!("Could not update tag cache.\n{e}"),
+        }
+    }
+
     fn sign_off(&self) -> Result<()> {
         log::info!("Signing off ...");
         match self.api_client.sign_off() {
@@ -64,32 +192,29 @@ impl Client {
             }
             Err(e) => {
                 log::warn!("Signing off failed.\n{e}");
-                self.play_sound("signoff_failed");
+                self.play_api_error_sound(&e, "signoff_failed");
             }
         }
         Ok(())
     }
 
-    fn handle_tag_read(&self, tag: &str) -> Result<Option<UserId>> {
-        log::debug!("Requesting details for tag {} ...", tag);
-        match self.api_client.get_tag_details(tag) {
+    fn handle_tag_read(&self, tag: &str) -> Result<Option<TagUser>> {
+        match self.get_tag_details(tag) {
             Ok(details) => match details {
                 Some(details) => {
+                    let user = details.user;
                     log::debug!(
                         "User for tag {}: {} (ID: {})",
                         details.identifier,
-                        details.user.screen_name.unwrap_or("<nameless>".to_string()),
-                        details.user.id
+                        user.screen_name.as_deref().unwrap_or("<nameless>"),
+                        user.id
                     );
-                    let user_id = details.user.id;
 
-                    if let Some(name) = details.sound_name {
-                        self.play_sound(&name);
-                    }
+                    self.greet(&user, details.sound_name.as_deref());
 
-                    log::debug!("Awaiting whereabouts for user {user_id} ...");
+                    log::debug!("Awaiting whereabouts for user {} ...", user.id);
 
-                    Ok(Some(user_id))
+                    Ok(Some(user))
                 }
                 None => {
                     log::info!("Unknown user tag: {tag}");
@@ -100,89 +225,324 @@ impl Client {
             },
             Err(e) => {
                 log::warn!("Requesting tag details failed.\n{e}");
-                self.play_sound("communication_failed");
+                self.play_api_error_sound(&e, "communication_failed");
 
                 Ok(None)
             }
         }
     }
 
+    /// Play the user's sound, or speak the greeting instead if the sound
+    /// is unavailable or none is assigned.
+    fn greet(&self, user: &TagUser, sound_name: Option<&str>) {
+        let greeting = self
+            .tts_config
+            .as_ref()
+            .and_then(|config| config.greeting_template.as_deref())
+            .map(|template| tts::render_template(template, user.screen_name.as_deref(), None));
+
+        match (sound_name, greeting) {
+            (Some(sound_name), greeting) => {
+                self.play_sound_or_speak(sound_name, greeting, SoundPriority::Low)
+            }
+            (None, Some(greeting)) => self.speak(greeting, SoundPriority::Low),
+            (None, None) => {}
+        }
+    }
+
+    /// Look up tag details, preferring fresh entries from the cache.
+    fn get_tag_details(&self, tag: &str) -> Result<Option<TagDetails>, ApiError> {
+        if let Some(tag_cache) = &self.tag_cache {
+            if let Some(details) = tag_cache.borrow().get(tag) {
+                log::debug!("Found details for tag {} in cache.", tag);
+                return Ok(Some(details.clone()));
+            }
+        }
+
+        log::debug!("Requesting details for tag {} ...", tag);
+        let details = self.api_client.get_tag_details(tag)?;
+
+        if let Some(tag_cache) = &self.tag_cache {
+            if let Err(e) = tag_cache.borrow_mut().update(tag, details.as_ref()) {
+                log::warn!("Could not update tag cache.\n{e}");
+            }
+        }
+
+        Ok(details)
+    }
+
     fn handle_button_press_with_identified_user(
         &self,
-        user_id: &UserId,
+        user: &TagUser,
         button: Button,
+        press: ButtonPress,
     ) -> Result<()> {
-        if let Some(whereabouts_name) = &self.party_config.buttons_to_whereabouts.get(&button) {
-            log::debug!("Submitting whereabouts for user {user_id} -> {whereabouts_name} ...");
+        if let Some(whereabouts_name) = &self.party_config.get_whereabouts_name(&button, press) {
+            log::debug!(
+                "Submitting whereabouts for user {} -> {whereabouts_name} ...",
+                user.id
+            );
+
+            let status_update = self
+                .api_client
+                .build_status_update(&user.id, whereabouts_name);
+
+            if let Some(status_queue) = &self.status_queue {
+                // Do not overtake status updates that are still queued.
+                if !status_queue.is_empty() {
+                    self.queue_status_update(status_queue, status_update);
+                    return Ok(());
+                }
+            }
 
-            let response = self.update_status(user_id, whereabouts_name);
+            let response = self.update_status(&status_update);
             match response {
                 Ok(_) => {
                     log::debug!("Status successfully updated.");
 
-                    let sound_name =
-                        match &self.party_config.whereabouts_sounds.get(*whereabouts_name) {
-                            Some(sound_names) => &self.random.choose_random_element(sound_names),
-                            None => "status_changed",
-                        };
-                    self.play_sound(sound_name);
-                }
-                Err(e) => {
-                    log::warn!("Status update failed.\n{e}");
-                    self.play_sound("communication_failed");
+                    self.confirm_whereabouts(user, whereabouts_name);
                 }
+                Err(e) => match &self.status_queue {
+                    Some(status_queue) if e.is_transient() => {
+                        log::warn!("Status update failed, queueing it.\n{e}");
+                        self.queue_status_update(status_queue, status_update);
+                    }
+                    _ => {
+                        if let ApiError::UnprocessableEntity(_) = e {
+                            log::warn!(
+                                "Whereabouts '{whereabouts_name}' were rejected, check the party configuration."
+                            );
+                        }
+                        log::warn!("Status update failed.\n{e}");
+                        self.play_api_error_sound(&e, "communication_failed");
+                    }
+                },
             }
         }
         Ok(())
     }
 
+    /// Play one of the whereabouts' sounds or speak their text (if
+    /// configured), or else the generic confirmation sound.
+    fn confirm_whereabouts(&self, user: &TagUser, whereabouts_name: &str) {
+        let text = self
+            .tts_config
+            .as_ref()
+            .and_then(|config| config.whereabouts_templates.get(whereabouts_name))
+            .map(|template| {
+                tts::render_template(
+                    template,
+                    user.screen_name.as_deref(),
+                    Some(whereabouts_name),
+                )
+            });
+
+        match (
+            self.party_config.whereabouts_sounds.get(whereabouts_name),
+            text,
+        ) {
+            (Some(sound_names), text) => {
+                let sound_name = self.random.choose_random_element(sound_names);
+                self.play_sound_or_speak(&sound_name, text, SoundPriority::Normal);
+            }
+            (None, Some(text)) => self.speak(text, SoundPriority::Normal),
+            (None, None) => self.play_sound("status_changed"),
+        }
+    }
+
+    fn queue_status_update(&self, status_queue: &StatusQueueHandle, status_update: StatusUpdate) {
+        match status_queue.enqueue(status_update) {
+            Ok(()) => {
+                log::info!("Status update queued.");
+                self.play_sound("status_queued");
+            }
+            Err(e) => {
+                log::error!("Could not queue status update.\n{e}");
+                self.play_error_sound("communication_failed");
+            }
+        }
+    }
+
+    fn handle_input_device_disconnected(&self, device: InputDevice) {
+        log::warn!("Input device disconnected: {:?}", device);
+        self.health.set_device_connected(device, false);
+        match device {
+            InputDevice::Reader => self.play_error_sound("reader_disconnected"),
+            InputDevice::Buttons => self.play_error_sound("buttons_disconnected"),
+        }
+    }
+
+    fn handle_input_device_reconnected(&self, device: InputDevice) {
+        log::info!("Input device reconnected: {:?}", device);
+        self.health.set_device_connected(device, true);
+        match device {
+            InputDevice::Reader => self.play_sound("reader_reconnected"),
+            InputDevice::Buttons => self.play_sound("buttons_reconnected"),
+        }
+    }
+
     fn shutdown(&self) -> Result<()> {
         log::info!("Shutdown requested.");
         self.sign_off()?;
+        self.audio_player.wait_until_finished()?;
         log::info!("Shutting down ...");
         Ok(())
     }
 
-    fn update_status(&self, user_id: &UserId, whereabouts_name: &str) -> Result<()> {
-        self.api_client.update_status(user_id, whereabouts_name)
+    fn update_status(&self, status_update: &StatusUpdate) -> Result<(), ApiError> {
+        // Queue the update right away instead of retrying it inline.
+        match self.status_queue {
+            Some(_) => self.api_client.update_status_once(status_update),
+            None => self.api_client.update_status(status_update),
+        }
+    }
+
+    /// Play a sound for a failed API call. A rejected client token gets a
+    /// dedicated sound as the client has to be registered again.
+    fn play_api_error_sound(&self, error: &ApiError, sound_name: &str) {
+        if let ApiError::Unauthorized(_) = error {
+            log::error!(
+                "The client token was rejected. Register the client again (subcommand `register`) and put the new token into the configuration file."
+            );
+            if self.has_client_not_authorized_sound {
+                self.play_error_sound(CLIENT_NOT_AUTHORIZED_SOUND_NAME);
+            } else {
+                self.play_error_sound(sound_name);
+            }
+        } else {
+            self.play_error_sound(sound_name);
+        }
     }
 
     fn play_sound(&self, name: &str) {
-        if let Err(e) = self.audio_player.play(name) {
+        self.play_sound_with_priority(name, SoundPriority::Normal);
+    }
+
+    /// Play a sound that interrupts less important ones (e.g. a greeting).
+    fn play_error_sound(&self, name: &str) {
+        self.play_sound_with_priority(name, SoundPriority::High);
+    }
+
+    /// Play a sound, speaking the text configured for it if the sound is
+    /// unavailable.
+    fn play_sound_with_priority(&self, name: &str, priority: SoundPriority) {
+        let text = self
+            .tts_config
+            .as_ref()
+            .and_then(|config| config.sound_texts.get(name))
+            .cloned();
+        self.play_sound_or_speak(name, text, priority);
+    }
+
+    fn play_sound_or_speak(&self, name: &str, text: Option<String>, priority: SoundPriority) {
+        if let Err(e) = self.audio_player.play(name, text, priority) {
             log::warn!("Could not play sound: {e}");
         }
     }
+
+    fn speak(&self, text: String, priority: SoundPriority) {
+        if let Err(e) = self.audio_player.speak(text, priority) {
+            log::warn!("Could not speak text: {e}");
+        }
+    }
+}
+
+/// Return the names of the built-in sounds that can be played with the
+/// configured features.
+fn builtin_sound_names(
+    config: &Config,
+    user_mode: &UserMode,
+    open_devices: &[InputDevice],
+) -> Vec<&'static str> {
+    let mut names = BUILTIN_SOUND_NAMES.to_vec();
+
+    if let UserMode::MultiUser = user_mode {
+        names.push("unknown_user_tag");
+        if config.user_selection_timeout_in_seconds.is_some() {
+            names.push("user_selection_timed_out");
+        }
+    }
+    if config.status_queue.is_some() {
+        names.push("status_queued");
+    }
+    if open_devices.contains(&InputDevice::Reader) {
+        names.extend(["reader_disconnected", "reader_reconnected"]);
+    }
+    if open_devices.contains(&InputDevice::Buttons) {
+        names.extend(["buttons_disconnected", "buttons_reconnected"]);
+    }
+
+    names
+}
+
+/// Preload the built-in sounds as well as those configured for
+/// whereabouts, and report those that are unavailable.
+fn load_sound_library(
+    sounds_path: PathBuf,
+    builtin_sound_names: &[&str],
+    party_config: &PartyConfig,
+    require_all_sounds: bool,
+) -> Result<SoundLibrary> {
+    let mut sound_names: Vec<String> = builtin_sound_names
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    sound_names.extend(party_config.whereabouts_sounds.values().flatten().cloned());
+
+    let mut sound_lib = SoundLibrary::new(sounds_path);
+    let report = sound_lib.preload(&sound_names);
+    report.log();
+
+    ensure!(
+        !require_all_sounds || report.is_complete(),
+        "Sounds are missing or cannot be decoded, refusing to start."
+    );
+
+    // Optional, so preload it without requiring it.
+    sound_lib.preload(&[CLIENT_NOT_AUTHORIZED_SOUND_NAME.to_string()]);
+
+    Ok(sound_lib)
 }
 
 struct SingleUserClient {
     client: Client,
-    user_id: UserId,
+    user: TagUser,
 }
 
 impl SingleUserClient {
     fn new(client: Client, user_id: UserId) -> Result<Self> {
-        Ok(Self { client, user_id })
+        let user = TagUser {
+            id: user_id,
+            screen_name: None,
+        };
+        Ok(Self { client, user })
     }
 
     fn run(&self) -> Result<()> {
         self.client.sign_on()?;
 
-        self.handle_events(&self.user_id)?;
+        self.handle_events(&self.user)?;
 
         Ok(())
     }
 
-    fn handle_events(&self, user_id: &UserId) -> Result<()> {
+    fn handle_events(&self, user: &TagUser) -> Result<()> {
         for msg in self.client.event_receiver.iter() {
             match msg {
                 Event::TagRead { .. } => {
                     log::error!("Unexpected tag read event received.");
                 }
-                Event::ButtonPressed { button } => {
-                    log::debug!("Button pressed: {:?}", button);
+                Event::ButtonPressed { button, press } => {
+                    log::debug!("Button pressed: {} ({:?})", button, press);
 
                     self.client
-                        .handle_button_press_with_identified_user(user_id, button)?;
+                        .handle_button_press_with_identified_user(user, button, press)?;
+                }
+                Event::InputDeviceDisconnected { device } => {
+                    self.client.handle_input_device_disconnected(device);
+                }
+                Event::InputDeviceReconnected { device } => {
+                    self.client.handle_input_device_reconnected(device);
                 }
                 Event::ShutdownRequested => {
                     self.client.shutdown()?;
@@ -197,11 +557,15 @@ impl SingleUserClient {
 
 struct MultiUserClient {
     client: Client,
+    selection_timeout: Option<Duration>,
 }
 
 impl MultiUserClient {
-    fn new(client: Client) -> Result<Self> {
-        Ok(Self { client })
+    fn new(client: Client, selection_timeout: Option<Duration>) -> Result<Self> {
+        Ok(Self {
+            client,
+            selection_timeout,
+        })
     }
 
     fn run(&self) -> Result<()> {
@@ -213,25 +577,55 @@ impl MultiUserClient {
     }
 
     fn handle_events(&self) -> Result<()> {
-        let mut current_user_id: Option<UserId> = None;
+        let mut current_user: Option<TagUser> = None;
+        let mut selection_deadline: Option<Instant> = None;
+
+        loop {
+            let msg = match selection_deadline {
+                Some(deadline) => match self.client.event_receiver.recv_deadline(deadline) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => {
+                        log::info!("No whereabouts selected in time, discarding user.");
+                        self.client.play_sound("user_selection_timed_out");
+                        current_user = None;
+                        selection_deadline = None;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match self.client.event_receiver.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                },
+            };
 
-        for msg in self.client.event_receiver.iter() {
             match msg {
                 Event::TagRead { tag } => {
                     log::debug!("Tag read: {tag}");
-                    current_user_id = self.client.handle_tag_read(&tag)?;
+                    current_user = self.client.handle_tag_read(&tag)?;
+                    selection_deadline = current_user
+                        .as_ref()
+                        .and(self.selection_timeout)
+                        .map(|timeout| Instant::now() + timeout);
                 }
-                Event::ButtonPressed { button } => {
-                    log::debug!("Button pressed: {:?}", button);
+                Event::ButtonPressed { button, press } => {
+                    log::debug!("Button pressed: {} ({:?})", button, press);
 
                     // Submit if user has identified; ignore if no user has
                     // been specified.
-                    if let Some(user_id) = current_user_id {
+                    if let Some(user) = current_user {
                         self.client
-                            .handle_button_press_with_identified_user(&user_id, button)?;
-                        current_user_id = None; // reset
+                            .handle_button_press_with_identified_user(&user, button, press)?;
+                        current_user = None; // reset
+                        selection_deadline = None;
                     }
                 }
+                Event::InputDeviceDisconnected { device } => {
+                    self.client.handle_input_device_disconnected(device);
+                }
+                Event::InputDeviceReconnected { device } => {
+                    self.client.handle_input_device_reconnected(device);
+                }
                 Event::ShutdownRequested => {
                     self.client.shutdown()?;
                     break;
@@ -244,16 +638,194 @@ impl MultiUserClient {
 }
 
 pub fn run_client(
-    sounds_path: PathBuf,
-    api_config: &ApiConfig,
-    party_config: PartyConfig,
+    config: Config,
     event_receiver: Receiver<Event>,
     user_mode: &UserMode,
+    open_devices: &[InputDevice],
 ) -> Result<()> {
-    let client = Client::new(sounds_path, api_config, party_config, event_receiver)?;
+    let selection_timeout = config
+        .user_selection_timeout_in_seconds
+        .map(Duration::from_secs);
+
+    let sound_lib = load_sound_library(
+        config.sounds_path.clone(),
+        &builtin_sound_names(&config, user_mode, open_devices),
+        &config.party,
+        config.require_all_sounds,
+    )?;
+
+    let client = Client::new(config, sound_lib, event_receiver, open_devices)?;
 
     match user_mode {
         UserMode::SingleUser(user_id) => SingleUserClient::new(client, user_id.clone())?.run(),
-        UserMode::MultiUser => MultiUserClient::new(client)?.run(),
+        UserMode::MultiUser => MultiUserClient::new(client, selection_timeout)?.run(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StatusQueueConfig;
+    use crate::mockapi;
+    use std::path::Path;
+
+    const CONFIG: &str = r#"
+        button_input_device = "/dev/input/event1"
+        sounds_path = "sounds"
+
+        [buttons_to_key_codes]
+        button1 = "KEY_1"
+
+        [api]
+        base_url = "http://localhost:8080"
+        client_token = "secret"
+        tls_verify = true
+        timeout_in_seconds = 5
+
+        [party]
+        party_id = "party-1"
+        whereabouts_sounds = {}
+
+        [party.buttons_to_whereabouts]
+        button1 = "office"
+    "#;
+
+    #[test]
+    fn test_builtin_sounds_depend_on_features() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let user_mode = UserMode::SingleUser("user-1".to_string());
+
+        let names = builtin_sound_names(&config, &user_mode, &[]);
+
+        assert_eq!(names, BUILTIN_SOUND_NAMES);
+    }
+
+    #[test]
+    fn test_builtin_sounds_for_all_features() {
+        let config: Config = toml::from_str(&format!(
+            r#"
+            user_selection_timeout_in_seconds = 10
+            {CONFIG}
+            [status_queue]
+            path = "queue.jsonl"
+            retry_interval_in_seconds = 30
+            "#
+        ))
+        .unwrap();
+        let open_devices = [InputDevice::Reader, InputDevice::Buttons];
+
+        let names = builtin_sound_names(&config, &UserMode::MultiUser, &open_devices);
+
+        for name in [
+            "unknown_user_tag",
+            "user_selection_timed_out",
+            "status_queued",
+            "reader_disconnected",
+            "reader_reconnected",
+            "buttons_disconnected",
+            "buttons_reconnected",
+        ] {
+            assert!(names.contains(&name), "{name}");
+        }
+        assert!(!names.contains(&CLIENT_NOT_AUTHORIZED_SOUND_NAME));
+    }
+
+    const SCENARIO: &str = r#"
+        client_token = "secret"
+
+        [tags.0010597059]
+        user_id = "user-1"
+        screen_name = "Alice"
+        sound_name = "alice"
+    "#;
+
+    /// Run a multi-user client against the mock API, feeding it the
+    /// events, and return it along with the sounds it played.
+    fn run_multi_user_client(
+        scenario: &str,
+        status_queue_path: Option<&Path>,
+        events: Vec<Event>,
+    ) -> (MultiUserClient, Vec<String>) {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        config.api.base_url = mockapi::serve_in_background(scenario);
+        config.status_queue = status_queue_path.map(|path| StatusQueueConfig {
+            path: path.to_path_buf(),
+            retry_interval_in_seconds: 30,
+        });
+
+        let (event_sender, event_receiver) = flume::unbounded();
+        for event in events {
+            event_sender.send(event).unwrap();
+        }
+
+        let (audio_player, played_receiver) = AudioPlayer::recording();
+        let health = Arc::new(Health::new(&[]));
+        let client =
+            Client::with_audio_player(config, audio_player, false, health, event_receiver).unwrap();
+
+        let multi_user_client = MultiUserClient::new(client, None).unwrap();
+        multi_user_client.run().unwrap();
+
+        (multi_user_client, played_receiver.drain().collect())
+    }
+
+    fn tag_read_and_button_press() -> Vec<Event> {
+        vec![
+            Event::TagRead {
+                tag: "0010597059".to_string(),
+            },
+            Event::ButtonPressed {
+                button: Button::try_from("button1".to_string()).unwrap(),
+                press: ButtonPress::Short,
+            },
+            Event::ShutdownRequested,
+        ]
+    }
+
+    #[test]
+    fn test_status_is_updated_after_tag_read_and_button_press() {
+        let (_, played) = run_multi_user_client(SCENARIO, None, tag_read_and_button_press());
+
+        assert_eq!(
+            played,
+            [
+                "signon_successful",
+                "alice",
+                "status_changed",
+                "signoff_successful"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status_update_is_queued_on_server_error() {
+        let directory = tempfile::tempdir().unwrap();
+        let status_queue_path = directory.path().join("queue.jsonl");
+        let scenario = format!(
+            r#"
+            {SCENARIO}
+            [[faults]]
+            endpoint = "statuses"
+            status = 503
+            "#
+        );
+
+        let (multi_user_client, played) = run_multi_user_client(
+            &scenario,
+            Some(&status_queue_path),
+            tag_read_and_button_press(),
+        );
+
+        assert_eq!(
+            played,
+            [
+                "signon_successful",
+                "alice",
+                "status_queued",
+                "signoff_successful"
+            ]
+        );
+        let status_queue = multi_user_client.client.status_queue.as_ref().unwrap();
+        assert_eq!(status_queue.len(), 1);
     }
 }
@@ -7,27 +7,72 @@ use std::collections::HashMap;
 use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Result, ensure};
 use serde::Deserialize;
 
-use crate::buttons::Button;
+use crate::buttons::{Button, ButtonPress};
+use crate::keymap::KeyboardLayout;
 use crate::model::{PartyId, UserId, UserMode};
+use crate::serialreader::SerialFraming;
+use crate::tagnormalization::NormalizationStep;
 
 #[derive(Deserialize)]
 pub(crate) struct Config {
     pub reader_input_device: Option<String>,
+    #[serde(default)]
+    pub reader: ReaderConfig,
     pub button_input_device: String,
 
     #[serde(rename = "buttons_to_key_codes")]
     pub buttons_to_key_code_names: HashMap<Button, String>,
 
+    #[serde(default)]
+    pub buttons: ButtonsConfig,
+
     pub sounds_path: PathBuf,
+    /// Refuse to start if sounds are missing or cannot be decoded.
+    #[serde(default)]
+    pub require_all_sounds: bool,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    pub user_selection_timeout_in_seconds: Option<u64>,
     pub api: ApiConfig,
     pub party: PartyConfig,
     pub user: Option<UserConfig>,
+    pub status_queue: Option<StatusQueueConfig>,
+    pub tag_cache: Option<TagCacheConfig>,
+    pub heartbeat: Option<HeartbeatConfig>,
+    pub tts: Option<TtsConfig>,
 }
 
 impl Config {
+    /// Ensure that every button assigned to whereabouts is mapped to a
+    /// key code, and that configured features are supported by this
+    /// build.
+    fn validate(&self) -> Result<()> {
+        let party = &self.party;
+        let assigned_buttons = party
+            .buttons_to_whereabouts
+            .keys()
+            .chain(party.long_press_buttons_to_whereabouts.keys())
+            .chain(party.double_press_buttons_to_whereabouts.keys());
+
+        for button in assigned_buttons {
+            ensure!(
+                self.buttons_to_key_code_names.contains_key(button),
+                "Button '{}' is assigned to whereabouts, but no key code is configured for it in section `buttons_to_key_codes`.",
+                button
+            );
+        }
+
+        ensure!(
+            self.tts.is_none() || cfg!(feature = "wav"),
+            "Text-to-speech (section `tts`) requires support for WAV files, but this build lacks it. Rebuild with cargo feature `wav`."
+        );
+
+        Ok(())
+    }
+
     pub fn get_user_mode(&self) -> UserMode {
         self.user
             .as_ref()
@@ -36,21 +81,203 @@ impl Config {
     }
 }
 
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct ReaderConfig {
+    pub backend: ReaderBackend,
+    pub normalization: Vec<NormalizationStep>,
+    pub duplicate_suppression_window_in_ms: u64,
+    pub reannounce_duplicates: bool,
+
+    // evdev backend only
+    pub keyboard_layout: KeyboardLayout,
+    pub terminator_keys: Vec<String>,
+
+    // serial backend only
+    pub baud_rate: u32,
+    pub framing: SerialFraming,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        Self {
+            backend: ReaderBackend::default(),
+            normalization: Vec::new(),
+            duplicate_suppression_window_in_ms: 0,
+            reannounce_duplicates: false,
+            keyboard_layout: KeyboardLayout::default(),
+            terminator_keys: vec!["KEY_ENTER".to_string()],
+            baud_rate: 9600,
+            framing: SerialFraming::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReaderBackend {
+    /// Reader emulating a keyboard
+    #[default]
+    Evdev,
+
+    /// Reader attached to a serial port (UART, USB-CDC)
+    Serial,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct ButtonsConfig {
+    pub debounce_in_ms: u64,
+    pub long_press_threshold_in_ms: Option<u64>,
+    pub double_press_window_in_ms: Option<u64>,
+}
+
 #[derive(Deserialize)]
 pub(crate) struct ApiConfig {
     pub base_url: String,
     pub client_token: String,
     pub tls_verify: bool,
     pub timeout_in_seconds: u64,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct RetryConfig {
+    /// Total number of attempts (1 disables retries)
+    pub max_attempts: u32,
+    pub initial_backoff_in_ms: u64,
+    pub max_backoff_in_ms: u64,
+    /// Give up after this time since the first attempt started,
+    /// including the time spent waiting for responses.
+    pub deadline_in_ms: u64,
+    pub jitter: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_in_ms: 500,
+            max_backoff_in_ms: 4000,
+            deadline_in_ms: 10000,
+            jitter: true,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub(crate) struct PartyConfig {
     pub party_id: PartyId,
     pub buttons_to_whereabouts: HashMap<Button, String>,
+    #[serde(default)]
+    pub long_press_buttons_to_whereabouts: HashMap<Button, String>,
+    #[serde(default)]
+    pub double_press_buttons_to_whereabouts: HashMap<Button, String>,
     pub whereabouts_sounds: HashMap<String, Vec<String>>,
 }
 
+impl PartyConfig {
+    /// Return the whereabouts assigned to the kind of press, or else
+    /// those assigned to a short press of the button.
+    pub fn get_whereabouts_name(&self, button: &Button, press: ButtonPress) -> Option<&String> {
+        let press_specific = match press {
+            ButtonPress::Short => None,
+            ButtonPress::Long => self.long_press_buttons_to_whereabouts.get(button),
+            ButtonPress::Double => self.double_press_buttons_to_whereabouts.get(button),
+        };
+        press_specific.or_else(|| self.buttons_to_whereabouts.get(button))
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct StatusQueueConfig {
+    pub path: PathBuf,
+    pub retry_interval_in_seconds: u64,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TagCacheConfig {
+    pub path: PathBuf,
+    pub ttl_in_seconds: u64,
+    #[serde(default)]
+    pub prefetch: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct HeartbeatConfig {
+    /// Path relative to the API base URL
+    pub endpoint: String,
+    pub interval_in_seconds: u64,
+    /// Error (in the body of a 404 response) with which the API reports
+    /// that it does not know the client
+    pub client_unknown_error: String,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "client/heartbeat".to_string(),
+            interval_in_seconds: 60,
+            client_unknown_error: "client_unknown".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct AudioConfig {
+    /// Master volume (1.0 = as recorded)
+    pub volume: f32,
+    /// Volume factors for individual sounds, by sound name
+    pub sound_gains: HashMap<String, f32>,
+    pub quiet_hours: Option<QuietHoursConfig>,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            sound_gains: HashMap::new(),
+            quiet_hours: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct QuietHoursConfig {
+    /// Time of day (`HH:MM`) at which quiet hours begin
+    pub start: String,
+    /// Time of day (`HH:MM`) at which quiet hours end
+    pub end: String,
+    /// Offset of the times from UTC (e.g. `+02:00`); UTC if not set
+    pub utc_offset: Option<String>,
+    /// Volume factor applied on top of the master volume
+    pub volume: Option<f32>,
+    /// Play a short beep instead of each sound.
+    #[serde(default)]
+    pub beep: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TtsConfig {
+    /// Program and arguments; `{text}` and `{output}` are replaced with
+    /// the text to speak and the path of the WAV file to write.
+    pub command: Vec<String>,
+    /// Spoken when a user's tag is read and no (available) sound is
+    /// assigned to them, e.g. "Hello, {screen_name}!"
+    pub greeting_template: Option<String>,
+    /// Texts to speak in place of missing sounds, by sound name
+    #[serde(default)]
+    pub sound_texts: HashMap<String, String>,
+    /// Texts to speak after a status update if the whereabouts have no
+    /// (available) sound, by whereabouts name
+    #[serde(default)]
+    pub whereabouts_templates: HashMap<String, String>,
+}
+
 #[derive(Deserialize)]
 pub(crate) struct UserConfig {
     pub id: Option<UserId>,
@@ -59,5 +286,81 @@ pub(crate) struct UserConfig {
 pub(crate) fn load_config(path: &Path) -> Result<Config> {
     let text = read_to_string(path)?;
     let config: Config = toml::from_str(&text)?;
+    config.validate()?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        button_input_device = "/dev/input/event1"
+        sounds_path = "sounds"
+
+        [buttons_to_key_codes]
+        button1 = "KEY_1"
+        button2 = "KEY_2"
+
+        [api]
+        base_url = "http://localhost:8080"
+        client_token = "secret"
+        tls_verify = true
+        timeout_in_seconds = 5
+
+        [party]
+        party_id = "party-1"
+        whereabouts_sounds = {}
+
+        [party.buttons_to_whereabouts]
+        button1 = "office"
+        button2 = "kitchen"
+
+        [party.long_press_buttons_to_whereabouts]
+        button1 = "sleeping"
+    "#;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn test_tts_requires_wav_support() {
+        let config = parse(&format!("{CONFIG}\n[tts]\ncommand = [\"espeak-ng\"]\n"));
+
+        let result = config.validate();
+
+        if cfg!(feature = "wav") {
+            assert!(result.is_ok());
+        } else {
+            let message = result.unwrap_err().to_string();
+            assert!(message.contains("cargo feature `wav`"), "{message}");
+        }
+    }
+
+    #[test]
+    fn test_whereabouts_for_press() {
+        let party = parse(CONFIG).party;
+        let button = |name: &str| Button::try_from(name.to_string()).unwrap();
+        let whereabouts = |name, press| {
+            party
+                .get_whereabouts_name(&button(name), press)
+                .map(String::as_str)
+        };
+
+        assert_eq!(whereabouts("button1", ButtonPress::Short), Some("office"));
+        assert_eq!(whereabouts("button1", ButtonPress::Long), Some("sleeping"));
+        assert_eq!(whereabouts("button3", ButtonPress::Short), None);
+    }
+
+    #[test]
+    fn test_whereabouts_fall_back_to_short_press() {
+        let party = parse(CONFIG).party;
+        let button = Button::try_from("button2".to_string()).unwrap();
+
+        for press in [ButtonPress::Long, ButtonPress::Double] {
+            let whereabouts = party.get_whereabouts_name(&button, press);
+            assert_eq!(whereabouts.map(String::as_str), Some("kitchen"));
+        }
+    }
+}
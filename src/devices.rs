@@ -3,11 +3,218 @@
  * License: MIT
  */
 
-use anyhow::{Result, anyhow};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
 use evdev::Device;
+use flume::Sender;
+
+use crate::events::{Event, InputDevice};
+
+/// How long to wait between attempts to reopen a disconnected device
+pub(crate) const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Specifies which input device to use: by path (e.g.
+/// `/dev/input/event23`), by name (`name:<device name>`), by USB vendor
+/// and product ID (`usb:<vendor ID>:<product ID>`, in hexadecimal), or by
+/// physical path (`phys:<physical path>`).
+#[derive(Clone, Debug)]
+pub(crate) enum DeviceSelector {
+    Path(PathBuf),
+    Name(String),
+    UsbId { vendor_id: u16, product_id: u16 },
+    PhysicalPath(String),
+}
+
+impl DeviceSelector {
+    pub(crate) fn parse(value: &str) -> Result<Self> {
+        if let Some(name) = value.strip_prefix("name:") {
+            return Ok(Self::Name(name.to_string()));
+        }
+
+        if let Some(usb_id) = value.strip_prefix("usb:") {
+            let (vendor_id, product_id) = usb_id
+                .split_once(':')
+                .context("Expected USB ID in format `usb:<vendor ID>:<product ID>`")?;
+            return Ok(Self::UsbId {
+                vendor_id: u16::from_str_radix(vendor_id, 16)
+                    .with_context(|| format!("Invalid USB vendor ID '{}'", vendor_id))?,
+                product_id: u16::from_str_radix(product_id, 16)
+                    .with_context(|| format!("Invalid USB product ID '{}'", product_id))?,
+            });
+        }
+
+        if let Some(physical_path) = value.strip_prefix("phys:") {
+            return Ok(Self::PhysicalPath(physical_path.to_string()));
+        }
+
+        Ok(Self::Path(PathBuf::from(value)))
+    }
+
+    fn matches(&self, path: &PathBuf, device: &Device) -> bool {
+        match self {
+            Self::Path(selected_path) => selected_path == path,
+            Self::Name(name) => device.name() == Some(name.as_str()),
+            Self::UsbId {
+                vendor_id,
+                product_id,
+            } => {
+                let input_id = device.input_id();
+                input_id.vendor() == *vendor_id && input_id.product() == *product_id
+            }
+            Self::PhysicalPath(physical_path) => {
+                device.physical_path() == Some(physical_path.as_str())
+            }
+        }
+    }
+
+    /// Find the path of the single device matching the selector.
+    pub(crate) fn resolve(&self) -> Result<PathBuf> {
+        if let Self::Path(path) = self {
+            return Ok(path.clone());
+        }
+
+        let mut matching_paths: Vec<PathBuf> = evdev::enumerate()
+            .filter(|(path, device)| self.matches(path, device))
+            .map(|(path, _)| path)
+            .collect();
+        matching_paths.sort();
+
+        match matching_paths.len() {
+            0 => bail!("No input device matches {}.", self),
+            1 => Ok(matching_paths.remove(0)),
+            _ => bail!(
+                "Multiple input devices match {}: {}",
+                self,
+                matching_paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "path {}", path.display()),
+            Self::Name(name) => write!(f, "name \"{}\"", name),
+            Self::UsbId {
+                vendor_id,
+                product_id,
+            } => write!(f, "USB ID {:04x}:{:04x}", vendor_id, product_id),
+            Self::PhysicalPath(physical_path) => write!(f, "physical path {}", physical_path),
+        }
+    }
+}
+
+/// An input device that is reopened after it has been disconnected.
+pub(crate) struct ReconnectingInputDevice {
+    selector: DeviceSelector,
+    label: String,
+    kind: InputDevice,
+    /// Name reported by the device, used to find it again if its path
+    /// has changed
+    product_name: Option<String>,
+}
+
+impl ReconnectingInputDevice {
+    pub(crate) fn open(
+        device_name: String,
+        label: String,
+        kind: InputDevice,
+    ) -> Result<(Self, Device)> {
+        let selector = DeviceSelector::parse(&device_name)?;
+        let device = open_input_device(device_name, label.clone())?;
+        let product_name = device.name().map(|name| name.to_string());
+
+        let reconnecting_device = Self {
+            selector,
+            label,
+            kind,
+            product_name,
+        };
+
+        Ok((reconnecting_device, device))
+    }
+
+    /// Close the failed device, report the disconnection, then wait for
+    /// the device to reappear and reopen it.
+    pub(crate) fn reconnect(
+        &self,
+        device: Device,
+        error: io::Error,
+        sender: &Sender<Event>,
+    ) -> Result<Device> {
+        // Release the grab so the device node can be grabbed again.
+        drop(device);
+
+        log::warn!("Lost connection to {}: {}", self.label, error);
+        sender.send(Event::InputDeviceDisconnected { device: self.kind })?;
+
+        loop {
+            sleep(RECONNECT_INTERVAL);
+
+            let Some(path) = self.find_device() else {
+                continue;
+            };
+
+            match open_input_device(path.to_string_lossy().into_owned(), self.label.clone()) {
+                Ok(device) => {
+                    log::info!("Reconnected {}.", self.label);
+                    sender.send(Event::InputDeviceReconnected { device: self.kind })?;
+                    return Ok(device);
+                }
+                Err(e) => log::debug!("Could not reopen {}: {}", self.label, e),
+            }
+        }
+    }
+
+    /// Look for the device via its selector first. If selected by path,
+    /// fall back to looking it up by its name.
+    fn find_device(&self) -> Option<PathBuf> {
+        let DeviceSelector::Path(path) = &self.selector else {
+            return self
+                .selector
+                .resolve()
+                .inspect_err(|e| log::debug!("{e}"))
+                .ok();
+        };
+
+        if let Ok(device) = Device::open(path) {
+            if self.product_name.is_none() || device.name() == self.product_name.as_deref() {
+                return Some(path.clone());
+            }
+        }
+
+        let product_name = self.product_name.as_deref()?;
+        evdev::enumerate()
+            .find(|(_, device)| device.name() == Some(product_name))
+            .map(|(path, _)| path)
+    }
+}
+
+/// Return whether a failed read should simply be repeated (as opposed to
+/// the device having been disconnected).
+pub(crate) fn is_interruption(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
 
 pub(crate) fn open_input_device(device_name: String, label: String) -> Result<Device> {
-    Device::open(device_name)
+    let path = DeviceSelector::parse(&device_name)?
+        .resolve()
+        .with_context(|| format!("Could not find {}", label))?;
+
+    Device::open(path)
         .map_err(|e| anyhow!("Could not open {}: {}", label, e))
         .and_then(|mut device| {
             log::info!(
@@ -30,3 +237,51 @@ fn grab_input_device(device: &mut Device, label: String) -> Result<()> {
             log::info!("Successfully obtained exclusive access to {}.", label);
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_is_interruption() {
+        assert!(is_interruption(&io::Error::from(
+            io::ErrorKind::Interrupted
+        )));
+        assert!(is_interruption(&io::Error::from(io::ErrorKind::WouldBlock)));
+        assert!(is_interruption(&io::Error::from(nix::errno::Errno::EINTR)));
+        assert!(!is_interruption(&io::Error::from(
+            nix::errno::Errno::ENODEV
+        )));
+    }
+
+    #[test]
+    fn test_parse_device_selector() {
+        assert!(matches!(
+            DeviceSelector::parse("/dev/input/event23").unwrap(),
+            DeviceSelector::Path(path) if path == Path::new("/dev/input/event23")
+        ));
+        assert!(matches!(
+            DeviceSelector::parse("name:RFID Reader").unwrap(),
+            DeviceSelector::Name(name) if name == "RFID Reader"
+        ));
+        assert!(matches!(
+            DeviceSelector::parse("usb:08ff:0009").unwrap(),
+            DeviceSelector::UsbId {
+                vendor_id: 0x08ff,
+                product_id: 0x0009
+            }
+        ));
+        assert!(matches!(
+            DeviceSelector::parse("phys:usb-0000:00:14.0-1/input0").unwrap(),
+            DeviceSelector::PhysicalPath(physical_path) if physical_path == "usb-0000:00:14.0-1/input0"
+        ));
+    }
+
+    #[test]
+    fn test_parse_device_selector_rejects_invalid_usb_id() {
+        assert!(DeviceSelector::parse("usb:08ff").is_err());
+        assert!(DeviceSelector::parse("usb:xyz:0009").is_err());
+    }
+}
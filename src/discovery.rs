@@ -0,0 +1,213 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use evdev::{Device, KeyCode};
+
+use crate::keycodes;
+
+const BY_ID_PATH: &str = "/dev/input/by-id";
+
+/// Maximum number of supported key codes to show per device
+const MAX_KEYS_SHOWN: usize = 16;
+
+struct DeviceInfo {
+    path: PathBuf,
+    name: String,
+    physical_path: Option<String>,
+    vendor_id: u16,
+    product_id: u16,
+    by_id_links: Vec<PathBuf>,
+    keys: Vec<KeyCode>,
+}
+
+impl DeviceInfo {
+    fn new(path: PathBuf, device: &Device, by_id_links: &HashMap<PathBuf, Vec<PathBuf>>) -> Self {
+        let input_id = device.input_id();
+        let canonical_path = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+
+        Self {
+            name: device.name().unwrap_or("unnamed device").to_string(),
+            physical_path: device.physical_path().map(|phys| phys.to_string()),
+            vendor_id: input_id.vendor(),
+            product_id: input_id.product(),
+            by_id_links: by_id_links
+                .get(&canonical_path)
+                .cloned()
+                .unwrap_or_default(),
+            keys: device
+                .supported_keys()
+                .map(|keys| keys.iter().collect())
+                .unwrap_or_default(),
+            path,
+        }
+    }
+
+    /// Keyboard-like devices (digits and Enter) are likely tag readers.
+    fn is_likely_reader(&self) -> bool {
+        let required_keys = [
+            KeyCode::KEY_0,
+            KeyCode::KEY_1,
+            KeyCode::KEY_2,
+            KeyCode::KEY_3,
+            KeyCode::KEY_4,
+            KeyCode::KEY_5,
+            KeyCode::KEY_6,
+            KeyCode::KEY_7,
+            KeyCode::KEY_8,
+            KeyCode::KEY_9,
+            KeyCode::KEY_ENTER,
+        ];
+        required_keys.iter().all(|key| self.keys.contains(key))
+    }
+
+    /// Devices with `BTN_*` codes (gamepads, joysticks, mice) are likely
+    /// button devices.
+    fn is_likely_button_device(&self) -> bool {
+        !self.button_keys().is_empty()
+    }
+
+    fn button_keys(&self) -> Vec<KeyCode> {
+        self.keys
+            .iter()
+            .filter(|key| format!("{:?}", key).starts_with("BTN_"))
+            .copied()
+            .collect()
+    }
+
+    /// Prefer the stable by-ID path over the event device path.
+    fn preferred_path(&self) -> &Path {
+        self.by_id_links.first().unwrap_or(&self.path)
+    }
+
+    fn print(&self) {
+        println!("{}", self.path.display());
+        println!("  Name:     \"{}\"", self.name);
+        println!(
+            "  Phys:     {}",
+            self.physical_path.as_deref().unwrap_or("-")
+        );
+        println!("  USB ID:   {:04x}:{:04x}", self.vendor_id, self.product_id);
+        for link in &self.by_id_links {
+            println!("  By ID:    {}", link.display());
+        }
+        println!("  Keys:     {}", format_keys(&self.keys));
+
+        let mut kinds = Vec::new();
+        if self.is_likely_reader() {
+            kinds.push("tag reader (keyboard-like)");
+        }
+        if self.is_likely_button_device() {
+            kinds.push("button device");
+        }
+        if !kinds.is_empty() {
+            println!("  Likely:   {}", kinds.join(", "));
+        }
+        println!();
+    }
+}
+
+/// Print all input devices and, optionally, a configuration snippet
+/// based on the devices that look like a tag reader and a button device.
+pub(crate) fn list_devices(print_config_snippet: bool) {
+    let by_id_links = find_by_id_links();
+
+    let mut devices: Vec<DeviceInfo> = evdev::enumerate()
+        .map(|(path, device)| DeviceInfo::new(path, &device, &by_id_links))
+        .collect();
+    devices.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if devices.is_empty() {
+        println!(
+            "No input devices found. Make sure you have permission to read from /dev/input/event*."
+        );
+        return;
+    }
+
+    for device in &devices {
+        device.print();
+    }
+
+    if print_config_snippet {
+        print_snippet(&devices);
+    }
+}
+
+fn print_snippet(devices: &[DeviceInfo]) {
+    let reader = devices.iter().find(|device| device.is_likely_reader());
+    let button_device = devices
+        .iter()
+        .find(|device| device.is_likely_button_device() && !device.is_likely_reader());
+
+    println!("# Configuration snippet");
+    match reader {
+        Some(reader) => println!(
+            "reader_input_device = \"{}\"",
+            reader.preferred_path().display()
+        ),
+        None => println!("#reader_input_device = \"\" # no candidate found"),
+    }
+    match button_device {
+        Some(button_device) => {
+            println!(
+                "button_input_device = \"{}\"",
+                button_device.preferred_path().display()
+            );
+            println!();
+            println!("[buttons_to_key_codes]");
+            for (i, key) in button_device.button_keys().iter().enumerate() {
+                println!(
+                    "button{} = \"{}\"",
+                    i + 1,
+                    keycodes::get_key_code_name(*key)
+                );
+            }
+        }
+        None => println!("#button_input_device = \"\" # no candidate found"),
+    }
+}
+
+/// Map canonical device paths to their symlinks in `/dev/input/by-id`.
+fn find_by_id_links() -> HashMap<PathBuf, Vec<PathBuf>> {
+    let mut links: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+
+    let Ok(entries) = fs::read_dir(BY_ID_PATH) else {
+        return links;
+    };
+
+    for entry in entries.flatten() {
+        let link = entry.path();
+        if let Ok(target) = fs::canonicalize(&link) {
+            links.entry(target).or_default().push(link);
+        }
+    }
+
+    for paths in links.values_mut() {
+        paths.sort();
+    }
+
+    links
+}
+
+fn format_keys(keys: &[KeyCode]) -> String {
+    if keys.is_empty() {
+        return "-".to_string();
+    }
+
+    let names: Vec<String> = keys
+        .iter()
+        .take(MAX_KEYS_SHOWN)
+        .map(|key| format!("{:?}", key))
+        .collect();
+
+    if keys.len() > MAX_KEYS_SHOWN {
+        format!("{}, … ({} total)", names.join(", "), keys.len())
+    } else {
+        names.join(", ")
+    }
+}
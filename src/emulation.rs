@@ -0,0 +1,320 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, EventType, InputEvent, KeyCode};
+
+use crate::buttons::{Button, ButtonPress};
+use crate::config::{self, ButtonsConfig, ReaderConfig};
+use crate::events::Event;
+use crate::keycodes;
+use crate::keymap::{self, KeyboardLayout};
+use crate::simulation::{self, Command};
+
+const READER_DEVICE_NAME: &str = "Verbleiber virtual tag reader";
+const BUTTON_DEVICE_NAME: &str = "Verbleiber virtual buttons";
+
+/// Time a key is held down when typing or pressing a button
+const KEY_HOLD_DURATION: Duration = Duration::from_millis(20);
+
+/// Create virtual input devices (via uinput) for the tag reader and the
+/// buttons configured in the configuration file, then turn commands
+/// read from standard input (same syntax as in simulation mode) into
+/// key events of those devices.
+pub(crate) fn emulate_devices(config_filename: PathBuf) -> Result<()> {
+    let config = config::load_config(&config_filename)?;
+
+    let mut reader = VirtualReader::new(&config.reader)?;
+    let mut buttons = VirtualButtons::new(&config.buttons_to_key_code_names, &config.buttons)?;
+
+    println!("Created virtual input devices. Configure them via:");
+    println!();
+    println!("reader_input_device = \"name:{}\"", READER_DEVICE_NAME);
+    println!("button_input_device = \"name:{}\"", BUTTON_DEVICE_NAME);
+    println!();
+    println!("Enter commands (e.g. `tag 0012345678`, `button 1`, `button 2 long`):");
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = match simulation::parse_command(line) {
+            Ok(Command::Send(Event::TagRead { tag })) => reader.type_tag(&tag),
+            Ok(Command::Send(Event::ButtonPressed { button, press })) => {
+                buttons.press(&button, press)
+            }
+            Ok(Command::Send(_)) => {
+                log::warn!("Command is not supported when emulating devices.");
+                Ok(())
+            }
+            Ok(Command::Wait(duration)) => {
+                sleep(duration);
+                Ok(())
+            }
+            Ok(Command::Quit) => break,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            log::warn!("Could not execute command: {e:#}");
+        }
+    }
+
+    Ok(())
+}
+
+fn create_device(name: &str, keys: &AttributeSet<KeyCode>) -> Result<VirtualDevice> {
+    let mut device = VirtualDevice::builder()
+        .and_then(|builder| builder.name(name).with_keys(keys))
+        .and_then(|builder| builder.build())
+        .with_context(|| format!("Could not create virtual input device \"{}\"", name))?;
+
+    for path in device.enumerate_dev_nodes_blocking()? {
+        log::info!(
+            "Created virtual input device \"{}\" at {}.",
+            name,
+            path?.display()
+        );
+    }
+
+    Ok(device)
+}
+
+fn emit_key(device: &mut VirtualDevice, key_code: KeyCode, value: i32) -> io::Result<()> {
+    device.emit(&[InputEvent::new(EventType::KEY.0, key_code.0, value)])
+}
+
+/// A keyboard-emulating tag reader
+struct VirtualReader {
+    device: VirtualDevice,
+    keyboard_layout: KeyboardLayout,
+    terminator_key: KeyCode,
+}
+
+impl VirtualReader {
+    fn new(reader_config: &ReaderConfig) -> Result<Self> {
+        let keyboard_layout = reader_config.keyboard_layout;
+        let terminator_key = match reader_config.terminator_keys.first() {
+            Some(name) => keycodes::parse_key_code_name(name).context("Invalid terminator key")?,
+            None => bail!("No terminator key configured"),
+        };
+
+        let mut keys = AttributeSet::<KeyCode>::new();
+        for code in 0..=keycodes::MAX_KEY_CODE {
+            let key_code = KeyCode::new(code);
+            if keymap::get_char(keyboard_layout, key_code, false).is_some()
+                || keymap::is_shift_key(key_code)
+            {
+                keys.insert(key_code);
+            }
+        }
+        keys.insert(terminator_key);
+
+        let device = create_device(READER_DEVICE_NAME, &keys)?;
+
+        Ok(Self {
+            device,
+            keyboard_layout,
+            terminator_key,
+        })
+    }
+
+    fn type_tag(&mut self, tag: &str) -> Result<()> {
+        for ch in tag.chars() {
+            let Some((key_code, shift)) = keymap::find_key_code(self.keyboard_layout, ch) else {
+                bail!(
+                    "Character '{}' cannot be typed with the keyboard layout",
+                    ch
+                );
+            };
+            self.type_key(key_code, shift)?;
+        }
+        self.type_key(self.terminator_key, false)
+    }
+
+    fn type_key(&mut self, key_code: KeyCode, shift: bool) -> Result<()> {
+        if shift {
+            emit_key(&mut self.device, KeyCode::KEY_LEFTSHIFT, 1)?;
+        }
+        emit_key(&mut self.device, key_code, 1)?;
+        sleep(KEY_HOLD_DURATION);
+        emit_key(&mut self.device, key_code, 0)?;
+        if shift {
+            emit_key(&mut self.device, KeyCode::KEY_LEFTSHIFT, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// A button input device
+struct VirtualButtons {
+    device: VirtualDevice,
+    buttons_to_key_codes: HashMap<Button, KeyCode>,
+    short_press_duration: Duration,
+    long_press_duration: Duration,
+    pause_duration: Duration,
+}
+
+impl VirtualButtons {
+    fn new(
+        buttons_to_key_code_names: &HashMap<Button, String>,
+        buttons_config: &ButtonsConfig,
+    ) -> Result<Self> {
+        let mut buttons_to_key_codes = HashMap::new();
+        for (button, key_code_name) in buttons_to_key_code_names {
+            let key_code = keycodes::parse_key_code_name(key_code_name)
+                .with_context(|| format!("Invalid key code for {}", button))?;
+            buttons_to_key_codes.insert(button.clone(), key_code);
+        }
+
+        let keys = buttons_to_key_codes.values().copied().collect();
+        let device = create_device(BUTTON_DEVICE_NAME, &keys)?;
+
+        // Hold and pause long enough to not be mistaken for contact bounce.
+        let debounce = Duration::from_millis(buttons_config.debounce_in_ms);
+        let short_press_duration = debounce + KEY_HOLD_DURATION;
+        let long_press_duration = buttons_config
+            .long_press_threshold_in_ms
+            .map(|threshold| Duration::from_millis(threshold) + KEY_HOLD_DURATION)
+            .unwrap_or(short_press_duration);
+
+        Ok(Self {
+            device,
+            buttons_to_key_codes,
+            short_press_duration,
+            long_press_duration,
+            pause_duration: short_press_duration,
+        })
+    }
+
+    fn press(&mut self, button: &Button, press: ButtonPress) -> Result<()> {
+        let Some(key_code) = self.buttons_to_key_codes.get(button).copied() else {
+            bail!("No key code configured for {}", button);
+        };
+
+        match press {
+            ButtonPress::Short => self.hold(key_code, self.short_press_duration)?,
+            ButtonPress::Long => self.hold(key_code, self.long_press_duration)?,
+            ButtonPress::Double => {
+                self.hold(key_code, self.short_press_duration)?;
+                sleep(self.pause_duration);
+                self.hold(key_code, self.short_press_duration)?;
+            }
+        }
+
+        // Separate consecutive presses.
+        sleep(self.pause_duration);
+        Ok(())
+    }
+
+    fn hold(&mut self, key_code: KeyCode, duration: Duration) -> Result<()> {
+        emit_key(&mut self.device, key_code, 1)?;
+        sleep(duration);
+        emit_key(&mut self.device, key_code, 0)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use flume::Receiver;
+
+    use crate::buttons;
+    use crate::tagreader;
+
+    use super::*;
+
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn dev_node_path(device: &mut VirtualDevice) -> String {
+        let path = device
+            .enumerate_dev_nodes_blocking()
+            .unwrap()
+            .next()
+            .expect("virtual device has no device node")
+            .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn button(name: &str) -> Button {
+        Button::try_from(name.to_string()).unwrap()
+    }
+
+    fn expect_event(receiver: &Receiver<Event>, expected: Event) {
+        let event = receiver
+            .recv_timeout(EVENT_TIMEOUT)
+            .expect("no event received");
+        assert_eq!(event, expected);
+    }
+
+    #[test]
+    #[ignore = "requires /dev/uinput"]
+    fn test_tags_typed_on_virtual_reader_are_read() {
+        let reader_config = ReaderConfig::default();
+        let mut reader = VirtualReader::new(&reader_config).unwrap();
+        let path = dev_node_path(&mut reader.device);
+
+        let (sender, receiver) = flume::unbounded();
+        tagreader::handle_tag_reads(path, &reader_config, sender).unwrap();
+
+        for tag in ["0012345678", "04a1b2c3"] {
+            reader.type_tag(tag).unwrap();
+            expect_event(
+                &receiver,
+                Event::TagRead {
+                    tag: tag.to_string(),
+                },
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "requires /dev/uinput"]
+    fn test_buttons_pressed_on_virtual_device_are_detected() {
+        let buttons_to_key_code_names = HashMap::from([
+            (button("button1"), "trigger".to_string()),
+            (button("button2"), "thumb".to_string()),
+        ]);
+        let buttons_config = ButtonsConfig {
+            debounce_in_ms: 30,
+            long_press_threshold_in_ms: Some(400),
+            double_press_window_in_ms: Some(300),
+        };
+        let mut virtual_buttons =
+            VirtualButtons::new(&buttons_to_key_code_names, &buttons_config).unwrap();
+        let path = dev_node_path(&mut virtual_buttons.device);
+
+        let (sender, receiver) = flume::unbounded();
+        buttons::handle_button_presses(path, buttons_to_key_code_names, &buttons_config, sender)
+            .unwrap();
+
+        for (name, press) in [
+            ("button1", ButtonPress::Short),
+            ("button2", ButtonPress::Long),
+            ("button1", ButtonPress::Double),
+        ] {
+            virtual_buttons.press(&button(name), press).unwrap();
+            expect_event(
+                &receiver,
+                Event::ButtonPressed {
+                    button: button(name),
+                    press,
+                },
+            );
+        }
+    }
+}
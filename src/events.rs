@@ -3,10 +3,19 @@
  * License: MIT
  */
 
-use crate::buttons::Button;
+use crate::buttons::{Button, ButtonPress};
 
+#[derive(Debug, PartialEq)]
 pub(crate) enum Event {
     TagRead { tag: String },
-    ButtonPressed { button: Button },
+    ButtonPressed { button: Button, press: ButtonPress },
+    InputDeviceDisconnected { device: InputDevice },
+    InputDeviceReconnected { device: InputDevice },
     ShutdownRequested,
 }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum InputDevice {
+    Reader,
+    Buttons,
+}
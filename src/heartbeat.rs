@@ -0,0 +1,251 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use crate::api::{ApiClient, ApiError, ErrorDetails, Heartbeat};
+use crate::config::HeartbeatConfig;
+use crate::events::InputDevice;
+use crate::queue::StatusQueueHandle;
+
+/// Health of the devices attached to the client, as last observed by
+/// the client. Devices that have not been opened (e.g. the reader in
+/// single-user mode) are not reported.
+pub(crate) struct Health {
+    reader_connected: Option<AtomicBool>,
+    buttons_connected: Option<AtomicBool>,
+    audio_ok: AtomicBool,
+}
+
+impl Health {
+    pub(crate) fn new(open_devices: &[InputDevice]) -> Self {
+        let flag_if_open = |device| {
+            open_devices
+                .contains(&device)
+                .then(|| AtomicBool::new(true))
+        };
+
+        Self {
+            reader_connected: flag_if_open(InputDevice::Reader),
+            buttons_connected: flag_if_open(InputDevice::Buttons),
+            audio_ok: AtomicBool::new(true),
+        }
+    }
+
+    pub(crate) fn set_device_connected(&self, device: InputDevice, connected: bool) {
+        let flag = match device {
+            InputDevice::Reader => &self.reader_connected,
+            InputDevice::Buttons => &self.buttons_connected,
+        };
+        if let Some(flag) = flag {
+            flag.store(connected, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn set_audio_ok(&self, ok: bool) {
+        self.audio_ok.store(ok, Ordering::Relaxed);
+    }
+}
+
+/// Start a background thread that periodically reports the client's
+/// health to the API.
+pub(crate) fn start_heartbeat(
+    config: HeartbeatConfig,
+    api_client: ApiClient,
+    health: Arc<Health>,
+    status_queue: Option<StatusQueueHandle>,
+) {
+    let sender = HeartbeatSender {
+        endpoint: config.endpoint,
+        interval: Duration::from_secs(config.interval_in_seconds),
+        client_unknown_error: config.client_unknown_error,
+        api_client,
+        health,
+        status_queue,
+        started_at: Instant::now(),
+    };
+    thread::spawn(move || sender.run());
+}
+
+struct HeartbeatSender {
+    endpoint: String,
+    interval: Duration,
+    client_unknown_error: String,
+    api_client: ApiClient,
+    health: Arc<Health>,
+    status_queue: Option<StatusQueueHandle>,
+    started_at: Instant,
+}
+
+impl HeartbeatSender {
+    fn run(&self) {
+        loop {
+            sleep(self.interval);
+
+            if !self.send_heartbeat() {
+                break;
+            }
+        }
+    }
+
+    /// Send a heartbeat and react to the response. Return whether to
+    /// keep sending heartbeats.
+    fn send_heartbeat(&self) -> bool {
+        match self
+            .api_client
+            .send_heartbeat(&self.endpoint, &self.build_heartbeat())
+        {
+            Ok(()) => log::debug!("Heartbeat sent."),
+            Err(ApiError::NotFound(details)) if self.is_client_unknown(&details) => {
+                log::warn!("API does not know this client (anymore), signing on again ...");
+                match self.api_client.sign_on() {
+                    Ok(()) => log::info!("Signed on again."),
+                    Err(e) => log::warn!("Signing on again failed.\n{e}"),
+                }
+            }
+            Err(ApiError::NotFound(details)) => {
+                log::error!(
+                    "Heartbeat endpoint '{}' not found, no longer sending heartbeats. {details}",
+                    self.endpoint
+                );
+                return false;
+            }
+            Err(ApiError::Unauthorized(details)) => {
+                // The token will not be accepted until the client has
+                // been registered again (and restarted).
+                log::error!(
+                    "The client token was rejected, no longer sending heartbeats. {details}"
+                );
+                return false;
+            }
+            Err(e) => log::warn!("Sending heartbeat failed.\n{e}"),
+        }
+        true
+    }
+
+    /// Tell a response of an unknown client apart from one of a missing
+    /// endpoint by the error in the response body.
+    fn is_client_unknown(&self, details: &ErrorDetails) -> bool {
+        details.message.as_deref() == Some(self.client_unknown_error.as_str())
+    }
+
+    fn build_heartbeat(&self) -> Heartbeat {
+        Heartbeat {
+            uptime_in_seconds: self.started_at.elapsed().as_secs(),
+            version: env!("CARGO_PKG_VERSION"),
+            reader_connected: load(&self.health.reader_connected),
+            buttons_connected: load(&self.health.buttons_connected),
+            audio_ok: self.health.audio_ok.load(Ordering::Relaxed),
+            queued_status_updates: self
+                .status_queue
+                .as_ref()
+                .map_or(0, |status_queue| status_queue.len()),
+        }
+    }
+}
+
+fn load(flag: &Option<AtomicBool>) -> Option<bool> {
+    flag.as_ref().map(|flag| flag.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiConfig, RetryConfig};
+    use crate::mockapi::serve_in_background;
+
+    fn sender(api_client: ApiClient, health: Health) -> HeartbeatSender {
+        let config = HeartbeatConfig::default();
+        HeartbeatSender {
+            endpoint: config.endpoint,
+            interval: Duration::from_secs(config.interval_in_seconds),
+            client_unknown_error: config.client_unknown_error,
+            api_client,
+            health: Arc::new(health),
+            status_queue: None,
+            started_at: Instant::now(),
+        }
+    }
+
+    fn api_client(base_url: &str, client_token: &str) -> ApiClient {
+        let config = ApiConfig {
+            base_url: base_url.to_string(),
+            client_token: client_token.to_string(),
+            tls_verify: true,
+            timeout_in_seconds: 1,
+            retry: RetryConfig {
+                max_attempts: 1,
+                ..RetryConfig::default()
+            },
+        };
+        ApiClient::new(&config, "party".to_string())
+    }
+
+    fn heartbeat_json(health: Health) -> serde_json::Value {
+        let sender = sender(api_client("http://127.0.0.1:1", "secret"), health);
+        serde_json::to_value(sender.build_heartbeat()).unwrap()
+    }
+
+    #[test]
+    fn test_only_open_devices_are_reported() {
+        let heartbeat = heartbeat_json(Health::new(&[]));
+        assert!(heartbeat.get("reader_connected").is_none());
+        assert!(heartbeat.get("buttons_connected").is_none());
+
+        let health = Health::new(&[InputDevice::Buttons]);
+        health.set_device_connected(InputDevice::Reader, false);
+        health.set_device_connected(InputDevice::Buttons, false);
+        let heartbeat = heartbeat_json(health);
+        assert!(heartbeat.get("reader_connected").is_none());
+        assert_eq!(heartbeat["buttons_connected"], false);
+    }
+
+    #[test]
+    fn test_unknown_client_signs_on_again() {
+        let base_url = serve_in_background(r#"client_token = "secret""#);
+        let sender = sender(api_client(&base_url, "secret"), Health::new(&[]));
+
+        // The mock API does not know the client before it has signed on.
+        assert!(sender.send_heartbeat());
+        sender
+            .api_client
+            .send_heartbeat(&sender.endpoint, &sender.build_heartbeat())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_missing_endpoint_stops_heartbeats() {
+        let base_url = serve_in_background("");
+        let mut sender = sender(api_client(&base_url, "secret"), Health::new(&[]));
+        sender.endpoint = "client/no-such-endpoint".to_string();
+
+        assert!(!sender.send_heartbeat());
+    }
+
+    #[test]
+    fn test_rejected_token_stops_heartbeats() {
+        let base_url = serve_in_background(r#"client_token = "secret""#);
+        let sender = sender(api_client(&base_url, "outdated"), Health::new(&[]));
+
+        assert!(!sender.send_heartbeat());
+    }
+
+    #[test]
+    fn test_transient_failure_keeps_heartbeats() {
+        let base_url = serve_in_background(
+            r#"
+            [[faults]]
+            endpoint = "heartbeat"
+            status = 503
+            "#,
+        );
+        let sender = sender(api_client(&base_url, "secret"), Health::new(&[]));
+
+        assert!(sender.send_heartbeat());
+    }
+}
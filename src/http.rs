@@ -11,6 +11,8 @@ use ureq::tls::TlsConfig;
 pub(crate) fn build_agent(timeout: Duration, disable_tls_verification: bool) -> Agent {
     Agent::config_builder()
         .timeout_global(Some(timeout))
+        // Let the caller inspect error responses (see `api::check_response`).
+        .http_status_as_error(false)
         .tls_config(
             TlsConfig::builder()
                 .disable_verification(disable_tls_verification)
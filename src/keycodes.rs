@@ -0,0 +1,279 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use evdev::KeyCode;
+
+/// Highest key code defined by evdev (`BTN_TRIGGER_HAPPY40`)
+pub(crate) const MAX_KEY_CODE: u16 = 0x2e7;
+
+/// Find key code by short alias (e.g. `trigger`), evdev name (e.g.
+/// `KEY_KP1`, `BTN_TRIGGER_HAPPY20`), or number (e.g. `288`, for codes
+/// without a name).
+pub(crate) fn find_key_code_by_name(name: &str) -> Option<KeyCode> {
+    find_key_code_by_alias(name)
+        .or_else(|| KeyCode::from_str(&name.to_uppercase()).ok())
+        .or_else(|| {
+            name.parse()
+                .ok()
+                .filter(|code| *code <= MAX_KEY_CODE)
+                .map(KeyCode::new)
+        })
+}
+
+/// Return the preferred name of a key code: its short alias, if
+/// available, or else its evdev name, or else its number.
+pub(crate) fn get_key_code_name(key_code: KeyCode) -> String {
+    if let Some((alias, _)) = ALIASES
+        .iter()
+        .find(|(_, aliased_key_code)| *aliased_key_code == key_code)
+    {
+        return alias.to_string();
+    }
+
+    let name = format!("{:?}", key_code);
+    if name.starts_with("unknown") {
+        key_code.code().to_string()
+    } else {
+        name
+    }
+}
+
+/// Like `find_key_code_by_name`, but fail with a list of similar names
+/// if the name is unknown.
+pub(crate) fn parse_key_code_name(name: &str) -> Result<KeyCode> {
+    if let Some(key_code) = find_key_code_by_name(name) {
+        return Ok(key_code);
+    }
+
+    if let Ok(code) = name.parse::<u32>() {
+        bail!(
+            "Key code {} is out of range (maximum: {})",
+            code,
+            MAX_KEY_CODE
+        );
+    }
+
+    let suggestions = find_similar_names(name);
+    if suggestions.is_empty() {
+        bail!("Unknown key code name '{}'", name);
+    }
+    bail!(
+        "Unknown key code name '{}'. Did you mean one of these? {}",
+        name,
+        suggestions.join(", ")
+    );
+}
+
+/// Short aliases for key codes of mice, joysticks, and gamepads
+const ALIASES: &[(&str, KeyCode)] = &[
+    // mouse
+    ("left", KeyCode::BTN_LEFT),
+    ("right", KeyCode::BTN_RIGHT),
+    ("middle", KeyCode::BTN_MIDDLE),
+    ("side", KeyCode::BTN_SIDE),
+    ("extra", KeyCode::BTN_EXTRA),
+    ("forward", KeyCode::BTN_FORWARD),
+    ("back", KeyCode::BTN_BACK),
+    ("task", KeyCode::BTN_TASK),
+    // joystick
+    ("trigger", KeyCode::BTN_TRIGGER),
+    ("thumb", KeyCode::BTN_THUMB),
+    ("thumb2", KeyCode::BTN_THUMB2),
+    ("top", KeyCode::BTN_TOP),
+    ("top2", KeyCode::BTN_TOP2),
+    ("pinkie", KeyCode::BTN_PINKIE),
+    ("base", KeyCode::BTN_BASE),
+    ("base2", KeyCode::BTN_BASE2),
+    ("base3", KeyCode::BTN_BASE3),
+    ("base4", KeyCode::BTN_BASE4),
+    ("base5", KeyCode::BTN_BASE5),
+    ("base6", KeyCode::BTN_BASE6),
+    ("dead", KeyCode::BTN_DEAD),
+    // gamepad
+    ("south", KeyCode::BTN_SOUTH),
+    ("a", KeyCode::BTN_SOUTH),
+    ("east", KeyCode::BTN_EAST),
+    ("b", KeyCode::BTN_EAST),
+    ("c", KeyCode::BTN_C),
+    ("north", KeyCode::BTN_NORTH),
+    ("x", KeyCode::BTN_NORTH),
+    ("west", KeyCode::BTN_WEST),
+    ("y", KeyCode::BTN_WEST),
+    ("z", KeyCode::BTN_Z),
+    ("tl", KeyCode::BTN_TL),
+    ("tr", KeyCode::BTN_TR),
+    ("tl2", KeyCode::BTN_TL2),
+    ("tr2", KeyCode::BTN_TR2),
+    ("select", KeyCode::BTN_SELECT),
+    ("start", KeyCode::BTN_START),
+    ("mode", KeyCode::BTN_MODE),
+    ("thumbl", KeyCode::BTN_THUMBL),
+    ("thumbr", KeyCode::BTN_THUMBR),
+    // directional pad
+    ("dpad_up", KeyCode::BTN_DPAD_UP),
+    ("dpad_down", KeyCode::BTN_DPAD_DOWN),
+    ("dpad_left", KeyCode::BTN_DPAD_LEFT),
+    ("dpad_right", KeyCode::BTN_DPAD_RIGHT),
+    ("trigger_happy1", KeyCode::BTN_TRIGGER_HAPPY1),
+    ("trigger_happy2", KeyCode::BTN_TRIGGER_HAPPY2),
+    ("trigger_happy3", KeyCode::BTN_TRIGGER_HAPPY3),
+    ("trigger_happy4", KeyCode::BTN_TRIGGER_HAPPY4),
+    ("trigger_happy5", KeyCode::BTN_TRIGGER_HAPPY5),
+    ("trigger_happy6", KeyCode::BTN_TRIGGER_HAPPY6),
+    ("trigger_happy7", KeyCode::BTN_TRIGGER_HAPPY7),
+    ("trigger_happy8", KeyCode::BTN_TRIGGER_HAPPY8),
+];
+
+fn find_key_code_by_alias(name: &str) -> Option<KeyCode> {
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, key_code)| *key_code)
+}
+
+/// Return all known names (aliases first, then evdev names).
+fn all_names() -> Vec<String> {
+    let aliases = ALIASES.iter().map(|(alias, _)| alias.to_string());
+    let evdev_names = (0..=MAX_KEY_CODE)
+        .map(|code| format!("{:?}", KeyCode::new(code)))
+        .filter(|name| !name.starts_with("unknown"));
+    aliases.chain(evdev_names).collect()
+}
+
+fn find_similar_names(name: &str) -> Vec<String> {
+    let name = name.to_uppercase();
+    let max_distance = (name.len() / 3).max(1);
+
+    let mut candidates: Vec<(usize, String)> = all_names()
+        .into_iter()
+        .filter_map(|candidate| {
+            let upper_candidate = candidate.to_uppercase();
+            let distance = if upper_candidate.contains(&name) {
+                0
+            } else {
+                levenshtein_distance(&name, &upper_candidate)
+            };
+            (distance <= max_distance).then_some((distance, candidate))
+        })
+        .collect();
+
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(8)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+fn levenshtein_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..=b_chars.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current_row = vec![i + 1];
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let cost = usize::from(a_char != *b_char);
+            let value = (previous_row[j] + cost)
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+            current_row.push(value);
+        }
+        previous_row = current_row;
+    }
+
+    previous_row[b_chars.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_alias() {
+        assert_eq!(
+            parse_key_code_name("trigger").unwrap(),
+            KeyCode::BTN_TRIGGER
+        );
+        assert_eq!(parse_key_code_name("left").unwrap(), KeyCode::BTN_LEFT);
+    }
+
+    #[test]
+    fn test_parse_evdev_name() {
+        assert_eq!(parse_key_code_name("KEY_KP1").unwrap(), KeyCode::KEY_KP1);
+        assert_eq!(parse_key_code_name("key_kp1").unwrap(), KeyCode::KEY_KP1);
+        assert_eq!(
+            parse_key_code_name("BTN_TRIGGER_HAPPY20").unwrap(),
+            KeyCode::BTN_TRIGGER_HAPPY20
+        );
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_key_code_name("288").unwrap(), KeyCode::BTN_TRIGGER);
+        assert_eq!(
+            parse_key_code_name(&MAX_KEY_CODE.to_string()).unwrap(),
+            KeyCode::new(MAX_KEY_CODE)
+        );
+    }
+
+    #[test]
+    fn test_parse_number_above_maximum() {
+        let name = (MAX_KEY_CODE + 1).to_string();
+
+        let message = parse_key_code_name(&name).unwrap_err().to_string();
+
+        assert_eq!(
+            message,
+            format!(
+                "Key code {} is out of range (maximum: {})",
+                name, MAX_KEY_CODE
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_typo_suggests_similar_names() {
+        let message = parse_key_code_name("triger").unwrap_err().to_string();
+
+        assert!(message.starts_with("Unknown key code name 'triger'. Did you mean"));
+        assert!(message.contains("trigger"), "{message}");
+    }
+
+    #[test]
+    fn test_parse_unknown_name_without_suggestions() {
+        let message = parse_key_code_name("xyzzy-plugh-quux")
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(message, "Unknown key code name 'xyzzy-plugh-quux'");
+    }
+
+    #[test]
+    fn test_key_code_name_round_trip() {
+        for key_code in [
+            KeyCode::BTN_TRIGGER,
+            KeyCode::KEY_KP1,
+            KeyCode::BTN_TRIGGER_HAPPY20,
+            KeyCode::new(0x2e0),
+        ] {
+            let name = get_key_code_name(key_code);
+            assert_eq!(find_key_code_by_name(&name), Some(key_code), "{name}");
+        }
+        assert_eq!(get_key_code_name(KeyCode::BTN_TRIGGER), "trigger");
+        assert_eq!(get_key_code_name(KeyCode::KEY_KP1), "KEY_KP1");
+    }
+
+    #[test]
+    fn test_levenshtein_distance() {
+        assert_eq!(levenshtein_distance("", ""), 0);
+        assert_eq!(levenshtein_distance("KEY_A", "KEY_A"), 0);
+        assert_eq!(levenshtein_distance("KEY_A", "KEY_B"), 1);
+        assert_eq!(levenshtein_distance("TRIGER", "TRIGGER"), 1);
+        assert_eq!(levenshtein_distance("kitten", "sitting"), 3);
+    }
+}
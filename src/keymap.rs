@@ -0,0 +1,258 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use evdev::KeyCode;
+use serde::Deserialize;
+
+use crate::keycodes;
+
+/// Keyboard layout emulated by a reader (or barcode scanner)
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum KeyboardLayout {
+    #[default]
+    Us,
+    De,
+}
+
+/// Translate a key code to a character, considering the shift state.
+pub(crate) fn get_char(layout: KeyboardLayout, key_code: KeyCode, shift: bool) -> Option<char> {
+    if let Some(ch) = get_letter(layout, key_code) {
+        return Some(if shift { ch.to_ascii_uppercase() } else { ch });
+    }
+
+    if let Some(ch) = get_keypad_char(key_code) {
+        return Some(ch);
+    }
+
+    match layout {
+        KeyboardLayout::Us => get_us_char(key_code, shift),
+        KeyboardLayout::De => get_de_char(key_code, shift),
+    }
+}
+
+/// Find a key code (and whether shift has to be held) that produces the
+/// character.
+pub(crate) fn find_key_code(layout: KeyboardLayout, ch: char) -> Option<(KeyCode, bool)> {
+    (0..=keycodes::MAX_KEY_CODE)
+        .map(KeyCode::new)
+        .flat_map(|key_code| [(key_code, false), (key_code, true)])
+        .find(|(key_code, shift)| get_char(layout, *key_code, *shift) == Some(ch))
+}
+
+fn get_letter(layout: KeyboardLayout, key_code: KeyCode) -> Option<char> {
+    match key_code {
+        KeyCode::KEY_A => Some('a'),
+        KeyCode::KEY_B => Some('b'),
+        KeyCode::KEY_C => Some('c'),
+        KeyCode::KEY_D => Some('d'),
+        KeyCode::KEY_E => Some('e'),
+        KeyCode::KEY_F => Some('f'),
+        KeyCode::KEY_G => Some('g'),
+        KeyCode::KEY_H => Some('h'),
+        KeyCode::KEY_I => Some('i'),
+        KeyCode::KEY_J => Some('j'),
+        KeyCode::KEY_K => Some('k'),
+        KeyCode::KEY_L => Some('l'),
+        KeyCode::KEY_M => Some('m'),
+        KeyCode::KEY_N => Some('n'),
+        KeyCode::KEY_O => Some('o'),
+        KeyCode::KEY_P => Some('p'),
+        KeyCode::KEY_Q => Some('q'),
+        KeyCode::KEY_R => Some('r'),
+        KeyCode::KEY_S => Some('s'),
+        KeyCode::KEY_T => Some('t'),
+        KeyCode::KEY_U => Some('u'),
+        KeyCode::KEY_V => Some('v'),
+        KeyCode::KEY_W => Some('w'),
+        KeyCode::KEY_X => Some('x'),
+        // Y and Z are swapped on German keyboards.
+        KeyCode::KEY_Y => match layout {
+            KeyboardLayout::Us => Some('y'),
+            KeyboardLayout::De => Some('z'),
+        },
+        KeyCode::KEY_Z => match layout {
+            KeyboardLayout::Us => Some('z'),
+            KeyboardLayout::De => Some('y'),
+        },
+        _ => None,
+    }
+}
+
+fn get_keypad_char(key_code: KeyCode) -> Option<char> {
+    match key_code {
+        KeyCode::KEY_KP1 => Some('1'),
+        KeyCode::KEY_KP2 => Some('2'),
+        KeyCode::KEY_KP3 => Some('3'),
+        KeyCode::KEY_KP4 => Some('4'),
+        KeyCode::KEY_KP5 => Some('5'),
+        KeyCode::KEY_KP6 => Some('6'),
+        KeyCode::KEY_KP7 => Some('7'),
+        KeyCode::KEY_KP8 => Some('8'),
+        KeyCode::KEY_KP9 => Some('9'),
+        KeyCode::KEY_KP0 => Some('0'),
+        KeyCode::KEY_KPMINUS => Some('-'),
+        KeyCode::KEY_KPDOT => Some('.'),
+        _ => None,
+    }
+}
+
+fn get_us_char(key_code: KeyCode, shift: bool) -> Option<char> {
+    let (unshifted, shifted) = match key_code {
+        KeyCode::KEY_1 => ('1', '!'),
+        KeyCode::KEY_2 => ('2', '@'),
+        KeyCode::KEY_3 => ('3', '#'),
+        KeyCode::KEY_4 => ('4', '$'),
+        KeyCode::KEY_5 => ('5', '%'),
+        KeyCode::KEY_6 => ('6', '^'),
+        KeyCode::KEY_7 => ('7', '&'),
+        KeyCode::KEY_8 => ('8', '*'),
+        KeyCode::KEY_9 => ('9', '('),
+        KeyCode::KEY_0 => ('0', ')'),
+        KeyCode::KEY_MINUS => ('-', '_'),
+        KeyCode::KEY_EQUAL => ('=', '+'),
+        KeyCode::KEY_DOT => ('.', '>'),
+        KeyCode::KEY_COMMA => (',', '<'),
+        KeyCode::KEY_SLASH => ('/', '?'),
+        KeyCode::KEY_SEMICOLON => (';', ':'),
+        KeyCode::KEY_SPACE => (' ', ' '),
+        _ => return None,
+    };
+    Some(if shift { shifted } else { unshifted })
+}
+
+fn get_de_char(key_code: KeyCode, shift: bool) -> Option<char> {
+    let (unshifted, shifted) = match key_code {
+        KeyCode::KEY_1 => ('1', '!'),
+        KeyCode::KEY_2 => ('2', '"'),
+        KeyCode::KEY_3 => ('3', '§'),
+        KeyCode::KEY_4 => ('4', '$'),
+        KeyCode::KEY_5 => ('5', '%'),
+        KeyCode::KEY_6 => ('6', '&'),
+        KeyCode::KEY_7 => ('7', '/'),
+        KeyCode::KEY_8 => ('8', '('),
+        KeyCode::KEY_9 => ('9', ')'),
+        KeyCode::KEY_0 => ('0', '='),
+        KeyCode::KEY_SLASH => ('-', '_'),
+        KeyCode::KEY_RIGHTBRACE => ('+', '*'),
+        KeyCode::KEY_DOT => ('.', ':'),
+        KeyCode::KEY_COMMA => (',', ';'),
+        KeyCode::KEY_SPACE => (' ', ' '),
+        _ => return None,
+    };
+    Some(if shift { shifted } else { unshifted })
+}
+
+pub(crate) fn is_shift_key(key_code: KeyCode) -> bool {
+    matches!(key_code, KeyCode::KEY_LEFTSHIFT | KeyCode::KEY_RIGHTSHIFT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_char_letters_with_shift() {
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_A, false),
+            Some('a')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_A, true),
+            Some('A')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::De, KeyCode::KEY_F, true),
+            Some('F')
+        );
+    }
+
+    #[test]
+    fn test_get_char_swaps_y_and_z_on_german_layout() {
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_Y, false),
+            Some('y')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_Z, false),
+            Some('z')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::De, KeyCode::KEY_Y, false),
+            Some('z')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::De, KeyCode::KEY_Z, true),
+            Some('Y')
+        );
+    }
+
+    #[test]
+    fn test_get_char_digits_and_symbols_per_layout() {
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_1, false),
+            Some('1')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_2, true),
+            Some('@')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::De, KeyCode::KEY_2, true),
+            Some('"')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_MINUS, false),
+            Some('-')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::De, KeyCode::KEY_SLASH, false),
+            Some('-')
+        );
+        assert_eq!(
+            get_char(KeyboardLayout::De, KeyCode::KEY_DOT, true),
+            Some(':')
+        );
+    }
+
+    #[test]
+    fn test_get_char_keypad_ignores_shift_and_layout() {
+        for layout in [KeyboardLayout::Us, KeyboardLayout::De] {
+            assert_eq!(get_char(layout, KeyCode::KEY_KP5, false), Some('5'));
+            assert_eq!(get_char(layout, KeyCode::KEY_KP5, true), Some('5'));
+        }
+    }
+
+    #[test]
+    fn test_get_char_unmapped_key() {
+        assert_eq!(
+            get_char(KeyboardLayout::Us, KeyCode::KEY_ENTER, false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_key_code_round_trips() {
+        for layout in [KeyboardLayout::Us, KeyboardLayout::De] {
+            for ch in "azAZ09-.".chars() {
+                let (key_code, shift) = find_key_code(layout, ch).unwrap();
+                assert_eq!(get_char(layout, key_code, shift), Some(ch));
+            }
+        }
+
+        assert_eq!(
+            find_key_code(KeyboardLayout::De, 'z'),
+            Some((KeyCode::KEY_Y, false))
+        );
+        assert_eq!(find_key_code(KeyboardLayout::Us, '§'), None);
+    }
+
+    #[test]
+    fn test_is_shift_key() {
+        assert!(is_shift_key(KeyCode::KEY_LEFTSHIFT));
+        assert!(is_shift_key(KeyCode::KEY_RIGHTSHIFT));
+        assert!(!is_shift_key(KeyCode::KEY_LEFTCTRL));
+    }
+}
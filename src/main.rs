@@ -11,20 +11,36 @@ use simple_logger::SimpleLogger;
 
 mod api;
 mod audio;
+mod buttonmapping;
 mod buttons;
 mod cli;
 mod client;
 mod config;
 mod devices;
+mod discovery;
+mod emulation;
 mod events;
+mod heartbeat;
 mod http;
+mod keycodes;
+mod keymap;
+mod mockapi;
 mod model;
+mod queue;
+mod quiethours;
 mod random;
 mod registration;
+mod retry;
+mod serialreader;
+mod simulation;
+mod tagcache;
+mod tagnormalization;
 mod tagreader;
+mod tts;
 
 use crate::client::run_client;
-use crate::events::Event;
+use crate::config::{Config, ReaderBackend};
+use crate::events::{Event, InputDevice};
 use crate::model::UserMode;
 
 fn main() -> Result<()> {
@@ -47,13 +63,29 @@ fn main() -> Result<()> {
             audio_output,
             disable_tls_verification,
         )?,
-        cli::Command::Run { config_filename } => run(config_filename)?,
+        cli::Command::Devices { config_snippet } => discovery::list_devices(config_snippet),
+        cli::Command::MapButtons {
+            device,
+            button_count,
+            output_filename,
+        } => buttonmapping::map_buttons(device, button_count, output_filename)?,
+        cli::Command::EmulateDevices { config_filename } => {
+            emulation::emulate_devices(config_filename)?
+        }
+        cli::Command::MockApi {
+            listen_address,
+            scenario_filename,
+        } => mockapi::serve(&listen_address, scenario_filename)?,
+        cli::Command::Run {
+            config_filename,
+            simulate,
+        } => run(config_filename, simulate)?,
     }
 
     Ok(())
 }
 
-fn run(config_filename: PathBuf) -> Result<()> {
+fn run(config_filename: PathBuf, simulate: Option<Option<PathBuf>>) -> Result<()> {
     let config = config::load_config(&config_filename)?;
 
     let user_mode = config.get_user_mode();
@@ -62,30 +94,54 @@ fn run(config_filename: PathBuf) -> Result<()> {
         UserMode::MultiUser => log::info!("Running in multi-user mode."),
     }
 
-    let sounds_path = config.sounds_path.clone();
-
     let (tx1, rx): (Sender<Event>, Receiver<Event>) = flume::unbounded();
     let tx2 = tx1.clone();
     let tx3 = tx1.clone();
 
     ctrlc::set_handler(move || handle_ctrl_c(&tx1)).expect("Could not set Ctrl-C handler");
 
+    let open_devices = if let Some(script_filename) = simulate {
+        simulation::handle_simulated_input(script_filename, tx2)?;
+        Vec::new()
+    } else {
+        open_input_devices(&config, &user_mode, tx2, tx3)?
+    };
+
+    run_client(config, rx, &user_mode, &open_devices)?;
+
+    Ok(())
+}
+
+fn open_input_devices(
+    config: &Config,
+    user_mode: &UserMode,
+    tx2: Sender<Event>,
+    tx3: Sender<Event>,
+) -> Result<Vec<InputDevice>> {
+    let mut open_devices = Vec::new();
+
     if let UserMode::MultiUser = user_mode {
-        match config.reader_input_device {
-            Some(device) => tagreader::handle_tag_reads(device, tx2)?,
+        match config.reader_input_device.clone() {
+            Some(device) => match config.reader.backend {
+                ReaderBackend::Evdev => tagreader::handle_tag_reads(device, &config.reader, tx2)?,
+                ReaderBackend::Serial => {
+                    serialreader::handle_tag_reads(device, &config.reader, tx2)?
+                }
+            },
             None => bail!("No reader device configured, but one is required in multi-user mode."),
         }
+        open_devices.push(InputDevice::Reader);
     }
 
     buttons::handle_button_presses(
-        config.button_input_device,
-        config.buttons_to_key_code_names,
+        config.button_input_device.clone(),
+        config.buttons_to_key_code_names.clone(),
+        &config.buttons,
         tx3,
     )?;
+    open_devices.push(InputDevice::Buttons);
 
-    run_client(sounds_path, &config.api, config.party, rx, &user_mode)?;
-
-    Ok(())
+    Ok(open_devices)
 }
 
 fn handle_ctrl_c(sender: &Sender<Event>) {
@@ -0,0 +1,634 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+/// Maximum size of a request head (request line and headers)
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Maximum number of headers per request
+const MAX_HEADERS: usize = 32;
+
+/// Maximum size of a request body
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Behavior of the mock API, loaded from a TOML file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Scenario {
+    /// If set, requests to client endpoints must carry this bearer token.
+    client_token: Option<String>,
+    tags: HashMap<String, MockTag>,
+    registration: MockRegistration,
+    faults: Vec<Fault>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MockTag {
+    user_id: String,
+    screen_name: Option<String>,
+    sound_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct MockRegistration {
+    client_id: String,
+    token: String,
+    /// Number of status polls answered with `pending` before the outcome
+    /// is reported
+    pending_polls: u32,
+    outcome: RegistrationOutcome,
+}
+
+impl Default for MockRegistration {
+    fn default() -> Self {
+        Self {
+            client_id: "mock-client".to_string(),
+            token: "mock-token".to_string(),
+            pending_polls: 0,
+            outcome: RegistrationOutcome::Approved,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RegistrationOutcome {
+    Approved,
+    Rejected,
+}
+
+/// A failure injected into responses of an endpoint
+#[derive(Clone, Debug, Deserialize)]
+struct Fault {
+    endpoint: Endpoint,
+    /// Respond with this status code instead of the regular response.
+    status: Option<u16>,
+    /// Response body (JSON) to send along with the status code
+    body: Option<String>,
+    /// Delay the response (e.g. to provoke client timeouts).
+    #[serde(default)]
+    delay_in_ms: u64,
+    /// Close the connection without responding.
+    #[serde(default)]
+    drop_connection: bool,
+    /// Apply to this many requests only (or to all, if not set).
+    times: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Endpoint {
+    SignOn,
+    SignOff,
+    Tags,
+    Tag,
+    Statuses,
+    Heartbeat,
+    Register,
+    RegistrationStatus,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    idempotency_key: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: Option<String>,
+}
+
+impl Response {
+    fn empty(status: u16) -> Self {
+        Self { status, body: None }
+    }
+
+    fn json(status: u16, value: Value) -> Self {
+        Self {
+            status,
+            body: Some(value.to_string()),
+        }
+    }
+}
+
+/// Serve a mock of the whereabouts API (including client registration)
+/// with responses and injected failures as defined by the scenario file.
+pub(crate) fn serve(listen_address: &str, scenario_filename: Option<PathBuf>) -> Result<()> {
+    let scenario = match scenario_filename {
+        Some(filename) => load_scenario(&filename)?,
+        None => Scenario::default(),
+    };
+
+    let listener = TcpListener::bind(listen_address)
+        .with_context(|| format!("Could not listen on {}", listen_address))?;
+    log::info!("Mock API listening on http://{}", listener.local_addr()?);
+
+    let mock_api = Arc::new(Mutex::new(MockApi::new(scenario)));
+    run(listener, mock_api);
+
+    Ok(())
+}
+
+/// Handle each incoming connection on a thread of its own.
+fn run(listener: TcpListener, mock_api: Arc<Mutex<MockApi>>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Could not accept connection: {e}");
+                continue;
+            }
+        };
+
+        let mock_api = mock_api.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &mock_api) {
+                log::warn!("Could not handle request: {e:#}");
+            }
+        });
+    }
+}
+
+fn load_scenario(filename: &Path) -> Result<Scenario> {
+    let text = read_to_string(filename)
+        .with_context(|| format!("Could not read scenario file {}", filename.display()))?;
+    let scenario = toml::from_str(&text)
+        .with_context(|| format!("Invalid scenario file {}", filename.display()))?;
+    Ok(scenario)
+}
+
+fn handle_connection(mut stream: TcpStream, mock_api: &Mutex<MockApi>) -> Result<()> {
+    let request = read_request(&mut stream)?;
+
+    let Some(endpoint) = route(&request.method, &request.path) else {
+        log::info!(
+            "{} {} -> 404 (no such endpoint)",
+            request.method,
+            request.path
+        );
+        return write_response(&mut stream, &Response::empty(404));
+    };
+
+    let fault = mock_api.lock().unwrap().take_fault(endpoint);
+    if let Some(fault) = &fault {
+        sleep(Duration::from_millis(fault.delay_in_ms));
+        if fault.drop_connection {
+            log::info!("{} {} -> connection dropped", request.method, request.path);
+            return Ok(());
+        }
+    }
+
+    let response = match fault.and_then(|fault| fault.status.map(|status| (status, fault.body))) {
+        Some((status, body)) => Response { status, body },
+        None => mock_api.lock().unwrap().respond(endpoint, &request),
+    };
+
+    log::info!("{} {} -> {}", request.method, request.path, response.status);
+    write_response(&mut stream, &response)
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut head = Vec::new();
+
+    // Read up to the empty line that ends the request head.
+    while !head.ends_with(b"\r\n\r\n") {
+        let n = reader.read_until(b'\n', &mut head)?;
+        if n == 0 {
+            bail!("Connection closed before end of request head");
+        }
+        if head.len() > MAX_HEAD_SIZE {
+            bail!("Request head too large");
+        }
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Request::new(&mut headers);
+    parsed.parse(&head).context("Malformed request")?;
+
+    let find_header = |name: &str| {
+        parsed
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| String::from_utf8_lossy(header.value).into_owned())
+    };
+
+    let content_length = match find_header("Content-Length") {
+        Some(value) => value.trim().parse().context("Invalid content length")?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_SIZE {
+        bail!("Request body too large");
+    }
+    let authorization = find_header("Authorization");
+    let idempotency_key = find_header("Idempotency-Key");
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method: parsed.method.unwrap_or_default().to_string(),
+        path: parsed.path.unwrap_or_default().to_string(),
+        authorization,
+        idempotency_key,
+        body,
+    })
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> Result<()> {
+    let body = response.body.as_deref().unwrap_or_default();
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        response.status,
+        reason_phrase(response.status),
+        body.len()
+    );
+    if response.body.is_some() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        422 => "Unprocessable Entity",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+fn route(method: &str, path: &str) -> Option<Endpoint> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["client", "sign_on"]) => Some(Endpoint::SignOn),
+        ("POST", ["client", "sign_off"]) => Some(Endpoint::SignOff),
+        ("GET", ["tags"]) => Some(Endpoint::Tags),
+        ("GET", ["tags", _]) => Some(Endpoint::Tag),
+        ("POST", ["statuses"]) => Some(Endpoint::Statuses),
+        ("POST", ["client", "heartbeat"]) => Some(Endpoint::Heartbeat),
+        ("POST", ["client", "register"]) => Some(Endpoint::Register),
+        ("GET", ["client", "registration_status", _]) => Some(Endpoint::RegistrationStatus),
+        _ => None,
+    }
+}
+
+struct MockApi {
+    scenario: Scenario,
+    registration_polls: u32,
+    applied_idempotency_keys: HashSet<String>,
+    signed_on: bool,
+}
+
+impl MockApi {
+    fn new(scenario: Scenario) -> Self {
+        Self {
+            scenario,
+            registration_polls: 0,
+            applied_idempotency_keys: HashSet::new(),
+            signed_on: false,
+        }
+    }
+
+    /// Return the first fault configured for the endpoint that has not
+    /// been used up yet.
+    fn take_fault(&mut self, endpoint: Endpoint) -> Option<Fault> {
+        let index = self
+            .scenario
+            .faults
+            .iter()
+            .position(|fault| fault.endpoint == endpoint && fault.times != Some(0))?;
+
+        let fault = &mut self.scenario.faults[index];
+        if let Some(times) = fault.times.as_mut() {
+            *times -= 1;
+        }
+
+        Some(fault.clone())
+    }
+
+    fn respond(&mut self, endpoint: Endpoint, request: &Request) -> Response {
+        let requires_token = !matches!(endpoint, Endpoint::Register | Endpoint::RegistrationStatus);
+        if requires_token && !self.is_authorized(request) {
+            return Response::empty(401);
+        }
+
+        match endpoint {
+            Endpoint::SignOn => {
+                self.signed_on = true;
+                Response::empty(204)
+            }
+            Endpoint::SignOff => {
+                self.signed_on = false;
+                Response::empty(204)
+            }
+            Endpoint::Tags => {
+                let tags = self
+                    .scenario
+                    .tags
+                    .iter()
+                    .map(|(identifier, tag)| tag_to_json(identifier, tag))
+                    .collect();
+                Response::json(200, Value::Array(tags))
+            }
+            Endpoint::Tag => {
+                let identifier = request.path.rsplit('/').next().unwrap_or_default();
+                match self.scenario.tags.get(identifier) {
+                    Some(tag) => Response::json(200, tag_to_json(identifier, tag)),
+                    None => Response::empty(404),
+                }
+            }
+            Endpoint::Statuses => {
+                if let Some(key) = &request.idempotency_key {
+                    if !self.applied_idempotency_keys.insert(key.clone()) {
+                        log::info!("Ignoring repeated status update (idempotency key {key}).");
+                        return Response::empty(204);
+                    }
+                }
+
+                match serde_json::from_slice::<Value>(&request.body) {
+                    Ok(status_update) => log::info!("Received status update: {status_update}"),
+                    Err(e) => {
+                        log::warn!("Received malformed status update: {e}");
+                        return Response::empty(400);
+                    }
+                }
+                Response::empty(204)
+            }
+            Endpoint::Heartbeat => {
+                // Report the client as unknown until it has signed on.
+                if !self.signed_on {
+                    return Response::json(404, json!({ "error": "client_unknown" }));
+                }
+
+                match serde_json::from_slice::<Value>(&request.body) {
+                    Ok(heartbeat) => log::info!("Received heartbeat: {heartbeat}"),
+                    Err(e) => {
+                        log::warn!("Received malformed heartbeat: {e}");
+                        return Response::empty(400);
+                    }
+                }
+                Response::empty(204)
+            }
+            Endpoint::Register => {
+                let registration = &self.scenario.registration;
+                Response::json(
+                    200,
+                    json!({
+                        "client_id": registration.client_id,
+                        "token": registration.token,
+                    }),
+                )
+            }
+            Endpoint::RegistrationStatus => {
+                let registration = &self.scenario.registration;
+                let status = if self.registration_polls < registration.pending_polls {
+                    self.registration_polls += 1;
+                    "pending"
+                } else {
+                    match registration.outcome {
+                        RegistrationOutcome::Approved => "approved",
+                        RegistrationOutcome::Rejected => "rejected",
+                    }
+                };
+                Response::json(200, json!({ "status": status }))
+            }
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        match &self.scenario.client_token {
+            Some(token) => request.authorization.as_deref() == Some(&format!("Bearer {}", token)),
+            None => true,
+        }
+    }
+}
+
+fn tag_to_json(identifier: &str, tag: &MockTag) -> Value {
+    json!({
+        "identifier": identifier,
+        "user": {
+            "id": tag.user_id,
+            "screen_name": tag.screen_name,
+        },
+        "sound_name": tag.sound_name,
+    })
+}
+
+/// Serve the scenario on an ephemeral local port in the background and
+/// return the base URL.
+#[cfg(test)]
+pub(crate) fn serve_in_background(scenario: &str) -> String {
+    tests::start(scenario).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiClient, ApiError};
+    use crate::config::{ApiConfig, RetryConfig};
+
+    pub(super) fn start(scenario: &str) -> (String, Arc<Mutex<MockApi>>) {
+        let scenario = toml::from_str(scenario).unwrap();
+        let mock_api = Arc::new(Mutex::new(MockApi::new(scenario)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server_mock_api = mock_api.clone();
+        thread::spawn(move || run(listener, server_mock_api));
+
+        (base_url, mock_api)
+    }
+
+    fn api_client(base_url: &str, retry: RetryConfig) -> ApiClient {
+        let config = ApiConfig {
+            base_url: base_url.to_string(),
+            client_token: "secret".to_string(),
+            tls_verify: true,
+            timeout_in_seconds: 1,
+            retry,
+        };
+        ApiClient::new(&config, "party".to_string())
+    }
+
+    fn no_retries() -> RetryConfig {
+        RetryConfig {
+            max_attempts: 1,
+            ..RetryConfig::default()
+        }
+    }
+
+    const TAGS: &str = r#"
+        client_token = "secret"
+
+        [tags.0010597059]
+        user_id = "user-1"
+        screen_name = "Alice"
+        sound_name = "alice.ogg"
+    "#;
+
+    #[test]
+    fn test_client_flow() {
+        let (base_url, mock_api) = start(TAGS);
+        let api_client = api_client(&base_url, no_retries());
+
+        api_client.sign_on().unwrap();
+        assert!(mock_api.lock().unwrap().signed_on);
+
+        let details = api_client.get_tag_details("0010597059").unwrap().unwrap();
+        assert_eq!(details.user.id, "user-1");
+        assert_eq!(details.user.screen_name.as_deref(), Some("Alice"));
+        assert_eq!(details.sound_name.as_deref(), Some("alice.ogg"));
+        assert!(api_client.get_tag_details("unknown").unwrap().is_none());
+
+        let status_update = api_client.build_status_update(&details.user.id, "office");
+        api_client.update_status(&status_update).unwrap();
+        assert!(
+            mock_api
+                .lock()
+                .unwrap()
+                .applied_idempotency_keys
+                .contains(&status_update.idempotency_key)
+        );
+
+        api_client.sign_off().unwrap();
+        assert!(!mock_api.lock().unwrap().signed_on);
+    }
+
+    #[test]
+    fn test_wrong_client_token_is_rejected() {
+        let (base_url, _) = start(r#"client_token = "other""#);
+        let api_client = api_client(&base_url, no_retries());
+
+        let result = api_client.sign_on();
+        assert!(matches!(result, Err(ApiError::Unauthorized(_))));
+    }
+
+    #[test]
+    fn test_server_error_is_reported() {
+        let (base_url, _) = start(
+            r#"
+            [[faults]]
+            endpoint = "sign_on"
+            status = 503
+            body = '{"error": "maintenance"}'
+            "#,
+        );
+        let api_client = api_client(&base_url, no_retries());
+
+        match api_client.sign_on() {
+            Err(ApiError::ServerError(details)) => {
+                assert_eq!(details.status, 503);
+                assert_eq!(details.message.as_deref(), Some("maintenance"));
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_transient_fault_is_retried() {
+        let (base_url, mock_api) = start(
+            r#"
+            [[faults]]
+            endpoint = "sign_on"
+            status = 500
+            times = 2
+            "#,
+        );
+        let api_client = api_client(
+            &base_url,
+            RetryConfig {
+                max_attempts: 3,
+                initial_backoff_in_ms: 10,
+                max_backoff_in_ms: 10,
+                deadline_in_ms: 1000,
+                jitter: false,
+            },
+        );
+
+        api_client.sign_on().unwrap();
+        assert!(mock_api.lock().unwrap().signed_on);
+    }
+
+    #[test]
+    fn test_dropped_connection_is_a_network_error() {
+        let (base_url, _) = start(
+            r#"
+            [[faults]]
+            endpoint = "tag"
+            drop_connection = true
+            "#,
+        );
+        let api_client = api_client(&base_url, no_retries());
+
+        let result = api_client.get_tag_details("0010597059");
+        assert!(matches!(result, Err(ApiError::Network(_))));
+    }
+
+    #[test]
+    fn test_delayed_response_times_out() {
+        let (base_url, _) = start(
+            r#"
+            [[faults]]
+            endpoint = "sign_on"
+            delay_in_ms = 1500
+            "#,
+        );
+        let api_client = api_client(&base_url, no_retries());
+
+        let result = api_client.sign_on();
+        assert!(matches!(result, Err(ApiError::Timeout(_))));
+    }
+
+    #[test]
+    fn test_oversized_body_is_refused() {
+        let (base_url, mock_api) = start("");
+        let address = base_url.trim_start_matches("http://");
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /statuses HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        )
+        .unwrap();
+
+        // The connection is closed without a response.
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty());
+        assert!(mock_api.lock().unwrap().applied_idempotency_keys.is_empty());
+    }
+}
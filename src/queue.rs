@@ -0,0 +1,428 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use flume::{Receiver, RecvTimeoutError, Sender};
+
+use crate::api::{ApiClient, ApiError, StatusUpdate};
+
+/// Status updates that could not be submitted yet, persisted to a
+/// journal file (one JSON document per line) so they survive restarts.
+pub(crate) struct StatusQueue {
+    path: PathBuf,
+    updates: VecDeque<StatusUpdate>,
+}
+
+impl StatusQueue {
+    /// Load the queued updates. Entries that cannot be parsed (e.g. a
+    /// line torn by a crash while appending) are skipped, and the journal
+    /// is rewritten without them.
+    pub(crate) fn load(path: PathBuf) -> Result<Self> {
+        let mut updates = VecDeque::new();
+        let mut skipped_lines = 0;
+
+        if path.exists() {
+            let file = File::open(&path)
+                .with_context(|| format!("Could not open status queue file {}", path.display()))?;
+            for (index, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(update) => updates.push_back(update),
+                    Err(e) => {
+                        log::warn!(
+                            "Skipping invalid entry in line {} of status queue file {}: {e}",
+                            index + 1,
+                            path.display()
+                        );
+                        skipped_lines += 1;
+                    }
+                }
+            }
+        }
+
+        let queue = Self { path, updates };
+
+        // Do not let the next entry be appended to a torn line.
+        if skipped_lines > 0 {
+            queue.write(queue.updates.iter()).with_context(|| {
+                format!(
+                    "Could not rewrite status queue file {}",
+                    queue.path.display()
+                )
+            })?;
+        }
+
+        Ok(queue)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    fn enqueue(&mut self, update: StatusUpdate) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&update)?)?;
+        file.sync_all()?;
+
+        self.updates.push_back(update);
+        Ok(())
+    }
+
+    fn front(&self) -> Option<&StatusUpdate> {
+        self.updates.front()
+    }
+
+    /// Remove the first update, from the journal file first so that
+    /// memory and disk stay consistent if writing fails.
+    fn remove_front(&mut self) -> Result<()> {
+        self.write(self.updates.iter().skip(1))?;
+        self.updates.pop_front();
+        Ok(())
+    }
+
+    /// Rewrite the journal file with the given updates.
+    fn write<'a>(&self, updates: impl Iterator<Item = &'a StatusUpdate>) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        for update in updates {
+            writeln!(writer, "{}", serde_json::to_string(update)?)?;
+        }
+        writer.into_inner()?.sync_all()?;
+
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Client-side access to the status queue.
+#[derive(Clone)]
+pub(crate) struct StatusQueueHandle {
+    queue: Arc<Mutex<StatusQueue>>,
+    wakeup_sender: Sender<()>,
+}
+
+impl StatusQueueHandle {
+    pub(crate) fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+
+    /// Add the status update to the queue and have the worker attempt
+    /// to submit it right away.
+    pub(crate) fn enqueue(&self, update: StatusUpdate) -> Result<()> {
+        self.queue.lock().unwrap().enqueue(update)?;
+        // Worker might be busy; it will pick up the update anyway.
+        let _ = self.wakeup_sender.try_send(());
+        Ok(())
+    }
+}
+
+/// Load the queue and start a background worker that replays queued
+/// status updates, in order, once the API is reachable again.
+pub(crate) fn start_status_queue(
+    path: PathBuf,
+    retry_interval: Duration,
+    api_client: ApiClient,
+) -> Result<StatusQueueHandle> {
+    let queue = StatusQueue::load(path)?;
+    if !queue.is_empty() {
+        log::info!("{} queued status update(s) pending.", queue.len());
+    }
+    let queue = Arc::new(Mutex::new(queue));
+
+    let (wakeup_sender, wakeup_receiver) = flume::bounded(1);
+
+    let worker = StatusQueueWorker {
+        queue: queue.clone(),
+        api_client,
+        retry_interval,
+        wakeup_receiver,
+    };
+    thread::spawn(move || worker.run());
+
+    Ok(StatusQueueHandle {
+        queue,
+        wakeup_sender,
+    })
+}
+
+struct StatusQueueWorker {
+    queue: Arc<Mutex<StatusQueue>>,
+    api_client: ApiClient,
+    retry_interval: Duration,
+    wakeup_receiver: Receiver<()>,
+}
+
+impl StatusQueueWorker {
+    fn run(&self) {
+        loop {
+            self.replay();
+
+            match self.wakeup_receiver.recv_timeout(self.retry_interval) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    fn replay(&self) {
+        replay(&self.queue, |update| {
+            self.api_client.update_status_once(update)
+        });
+    }
+}
+
+/// Submit queued status updates in order until the queue is empty or
+/// the API is unavailable or does not accept the client. Updates that
+/// were rejected as invalid are discarded.
+fn replay(
+    queue: &Mutex<StatusQueue>,
+    mut submit: impl FnMut(&StatusUpdate) -> Result<(), ApiError>,
+) {
+    loop {
+        // Do not hold the lock while talking to the API.
+        let update = match queue.lock().unwrap().front() {
+            Some(update) => update.clone(),
+            None => break,
+        };
+
+        match submit(&update) {
+            Ok(()) => {
+                log::info!(
+                    "Submitted queued status update for user {} -> {} (from {}).",
+                    update.user_id,
+                    update.whereabouts_name,
+                    update.occurred_at
+                );
+            }
+            Err(e) if e.is_transient() => {
+                log::debug!("API still unavailable, keeping status updates queued.\n{e}");
+                break;
+            }
+            Err(e @ (ApiError::UnprocessableEntity(_) | ApiError::NotFound(_))) => {
+                log::warn!(
+                    "Queued status update for user {} -> {} was rejected, discarding it.\n{e}",
+                    update.user_id,
+                    update.whereabouts_name
+                );
+            }
+            Err(e) => {
+                log::warn!("Status update not accepted, keeping status updates queued.\n{e}");
+                break;
+            }
+        }
+
+        if let Err(e) = queue.lock().unwrap().remove_front() {
+            log::error!("Could not update status queue file.\n{e}");
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use time::OffsetDateTime;
+
+    use crate::api::ErrorDetails;
+
+    use super::*;
+
+    fn status_update(whereabouts_name: &str) -> StatusUpdate {
+        StatusUpdate {
+            user_id: "user-1".to_string(),
+            party_id: "party-1".to_string(),
+            whereabouts_name: whereabouts_name.to_string(),
+            occurred_at: OffsetDateTime::now_utc(),
+            idempotency_key: format!("key-{whereabouts_name}"),
+        }
+    }
+
+    fn whereabouts_names(queue: &StatusQueue) -> Vec<String> {
+        queue
+            .updates
+            .iter()
+            .map(|update| update.whereabouts_name.clone())
+            .collect()
+    }
+
+    fn error(status: u16) -> ApiError {
+        let details = ErrorDetails {
+            status,
+            message: None,
+        };
+        match status {
+            401 => ApiError::Unauthorized(details),
+            403 => ApiError::Forbidden(details),
+            500..=599 => ApiError::ServerError(details),
+            _ => ApiError::UnprocessableEntity(details),
+        }
+    }
+
+    #[test]
+    fn test_enqueue_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let mut queue = StatusQueue::load(path.clone()).unwrap();
+        assert!(queue.is_empty());
+        queue.enqueue(status_update("office")).unwrap();
+        queue.enqueue(status_update("kitchen")).unwrap();
+
+        let loaded = StatusQueue::load(path).unwrap();
+        assert_eq!(whereabouts_names(&loaded), ["office", "kitchen"]);
+        assert_eq!(loaded.updates[0].idempotency_key, "key-office");
+    }
+
+    #[test]
+    fn test_load_skips_truncated_last_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let mut queue = StatusQueue::load(path.clone()).unwrap();
+        queue.enqueue(status_update("office")).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"user_id\": \"user-1\", \"party_").unwrap();
+
+        let mut loaded = StatusQueue::load(path.clone()).unwrap();
+        assert_eq!(whereabouts_names(&loaded), ["office"]);
+
+        // The torn line has been removed, so appending works again.
+        loaded.enqueue(status_update("kitchen")).unwrap();
+        let loaded = StatusQueue::load(path).unwrap();
+        assert_eq!(whereabouts_names(&loaded), ["office", "kitchen"]);
+    }
+
+    #[test]
+    fn test_remove_front_updates_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let mut queue = StatusQueue::load(path.clone()).unwrap();
+        queue.enqueue(status_update("office")).unwrap();
+        queue.enqueue(status_update("kitchen")).unwrap();
+        queue.remove_front().unwrap();
+
+        assert_eq!(whereabouts_names(&queue), ["kitchen"]);
+        let loaded = StatusQueue::load(path).unwrap();
+        assert_eq!(whereabouts_names(&loaded), ["kitchen"]);
+    }
+
+    #[test]
+    fn test_remove_front_keeps_entry_if_journal_cannot_be_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let mut queue = StatusQueue::load(path).unwrap();
+        queue.enqueue(status_update("office")).unwrap();
+        fs::remove_dir_all(dir.path()).unwrap();
+
+        assert!(queue.remove_front().is_err());
+        assert_eq!(whereabouts_names(&queue), ["office"]);
+    }
+
+    #[test]
+    fn test_replay_submits_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let mut queue = StatusQueue::load(path.clone()).unwrap();
+        for name in ["office", "kitchen", "sleeping"] {
+            queue.enqueue(status_update(name)).unwrap();
+        }
+        let queue = Mutex::new(queue);
+
+        let mut submitted = Vec::new();
+        replay(&queue, |update| {
+            submitted.push(update.whereabouts_name.clone());
+            Ok(())
+        });
+
+        assert_eq!(submitted, ["office", "kitchen", "sleeping"]);
+        assert!(queue.lock().unwrap().is_empty());
+        assert!(StatusQueue::load(path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replay_discards_rejected_and_keeps_transient_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let mut queue = StatusQueue::load(path.clone()).unwrap();
+        for name in ["unknown", "office", "kitchen"] {
+            queue.enqueue(status_update(name)).unwrap();
+        }
+        let queue = Mutex::new(queue);
+
+        let mut attempted = Vec::new();
+        replay(&queue, |update| {
+            attempted.push(update.whereabouts_name.clone());
+            match update.whereabouts_name.as_str() {
+                "unknown" => Err(error(422)),
+                _ => Err(error(503)),
+            }
+        });
+
+        // Replay stops at the first transient failure.
+        assert_eq!(attempted, ["unknown", "office"]);
+        assert_eq!(
+            whereabouts_names(&queue.lock().unwrap()),
+            ["office", "kitchen"]
+        );
+        let loaded = StatusQueue::load(path).unwrap();
+        assert_eq!(whereabouts_names(&loaded), ["office", "kitchen"]);
+    }
+
+    #[test]
+    fn test_replay_keeps_updates_if_client_is_not_accepted() {
+        for status in [401, 403] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("queue.jsonl");
+
+            let mut queue = StatusQueue::load(path.clone()).unwrap();
+            for name in ["office", "kitchen"] {
+                queue.enqueue(status_update(name)).unwrap();
+            }
+            let queue = Mutex::new(queue);
+
+            let mut attempted = Vec::new();
+            replay(&queue, |update| {
+                attempted.push(update.whereabouts_name.clone());
+                Err(error(status))
+            });
+
+            assert_eq!(attempted, ["office"]);
+            assert_eq!(
+                whereabouts_names(&queue.lock().unwrap()),
+                ["office", "kitchen"]
+            );
+            let loaded = StatusQueue::load(path).unwrap();
+            assert_eq!(whereabouts_names(&loaded), ["office", "kitchen"]);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, ensure};
+use time::{OffsetDateTime, Time, UtcOffset};
+
+use crate::config::QuietHoursConfig;
+
+/// A daily period during which sounds are played more quietly or
+/// replaced by beeps
+pub(crate) struct QuietHours {
+    start: Time,
+    end: Time,
+    utc_offset: UtcOffset,
+    pub volume: f32,
+    pub beep: bool,
+}
+
+impl QuietHours {
+    pub(crate) fn new(config: &QuietHoursConfig) -> Result<Self> {
+        ensure!(
+            config.beep || config.volume.is_some(),
+            "Quiet hours require a volume or beeps to be configured"
+        );
+        let volume = config.volume.unwrap_or(1.0);
+        ensure!(
+            (0.0..=1.0).contains(&volume),
+            "Quiet hours volume must be between 0 and 1"
+        );
+
+        let utc_offset = match &config.utc_offset {
+            Some(utc_offset) => parse_utc_offset(utc_offset)?,
+            None => UtcOffset::UTC,
+        };
+
+        Ok(Self {
+            start: parse_time_of_day(&config.start)?,
+            end: parse_time_of_day(&config.end)?,
+            utc_offset,
+            volume,
+            beep: config.beep,
+        })
+    }
+
+    pub(crate) fn is_active_now(&self) -> bool {
+        let now = OffsetDateTime::now_utc().to_offset(self.utc_offset);
+        self.is_active_at(now.time())
+    }
+
+    /// Quiet hours may span midnight (e.g. from 22:00 to 07:00). They
+    /// are never active if start and end are the same.
+    fn is_active_at(&self, time: Time) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// Parse a time of day in the format `HH:MM`.
+fn parse_time_of_day(value: &str) -> Result<Time> {
+    let (hours, minutes) = split_hours_and_minutes(value)
+        .with_context(|| format!("Invalid time of day \"{value}\", expected HH:MM"))?;
+    Time::from_hms(hours, minutes, 0)
+        .with_context(|| format!("Invalid time of day \"{value}\", expected HH:MM"))
+}
+
+/// Parse an offset from UTC in the format `+HH:MM` or `-HH:MM`.
+fn parse_utc_offset(value: &str) -> Result<UtcOffset> {
+    let error = || format!("Invalid UTC offset \"{value}\", expected +HH:MM or -HH:MM");
+
+    let (sign, rest) = match value.split_at_checked(1) {
+        Some(("+", rest)) => (1, rest),
+        Some(("-", rest)) => (-1, rest),
+        _ => return Err(anyhow!(error())),
+    };
+    let (hours, minutes): (i8, i8) = split_hours_and_minutes(rest).with_context(error)?;
+    ensure!(
+        (0..=23).contains(&hours) && (0..=59).contains(&minutes),
+        error()
+    );
+
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).with_context(error)
+}
+
+/// Split `HH:MM` into hours and minutes, both unsigned numbers.
+fn split_hours_and_minutes<T: FromStr>(value: &str) -> Option<(T, T)> {
+    let (hours, minutes) = value.split_once(':')?;
+    Some((parse_digits(hours)?, parse_digits(minutes)?))
+}
+
+fn parse_digits<T: FromStr>(value: &str) -> Option<T> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(start: &str, end: &str, volume: Option<f32>) -> QuietHoursConfig {
+        QuietHoursConfig {
+            start: start.to_string(),
+            end: end.to_string(),
+            utc_offset: None,
+            volume,
+            beep: false,
+        }
+    }
+
+    fn quiet_hours(start: &str, end: &str) -> QuietHours {
+        QuietHours::new(&config(start, end, Some(0.3))).unwrap()
+    }
+
+    fn time(hours: u8, minutes: u8) -> Time {
+        Time::from_hms(hours, minutes, 0).unwrap()
+    }
+
+    #[test]
+    fn test_within_a_day() {
+        let quiet_hours = quiet_hours("12:00", "14:30");
+
+        assert!(!quiet_hours.is_active_at(time(11, 59)));
+        assert!(quiet_hours.is_active_at(time(12, 0)));
+        assert!(quiet_hours.is_active_at(time(14, 29)));
+        assert!(!quiet_hours.is_active_at(time(14, 30)));
+    }
+
+    #[test]
+    fn test_spanning_midnight() {
+        let quiet_hours = quiet_hours("22:00", "07:00");
+
+        assert!(!quiet_hours.is_active_at(time(21, 59)));
+        assert!(quiet_hours.is_active_at(time(22, 0)));
+        assert!(quiet_hours.is_active_at(time(0, 0)));
+        assert!(quiet_hours.is_active_at(time(6, 59)));
+        assert!(!quiet_hours.is_active_at(time(7, 0)));
+        assert!(!quiet_hours.is_active_at(time(12, 0)));
+    }
+
+    #[test]
+    fn test_never_active_if_start_equals_end() {
+        let quiet_hours = quiet_hours("07:00", "07:00");
+
+        for hours in 0..24 {
+            assert!(!quiet_hours.is_active_at(time(hours, 0)));
+        }
+    }
+
+    #[test]
+    fn test_volume_is_limited() {
+        for volume in [0.0, 0.5, 1.0] {
+            assert!(QuietHours::new(&config("22:00", "07:00", Some(volume))).is_ok());
+        }
+        for volume in [-0.1, 1.1, 50.0, f32::NAN] {
+            let result = QuietHours::new(&config("22:00", "07:00", Some(volume)));
+            assert!(result.is_err(), "{volume}");
+        }
+    }
+
+    #[test]
+    fn test_valid_time_of_day() {
+        assert_eq!(parse_time_of_day("00:00").unwrap(), time(0, 0));
+        assert_eq!(parse_time_of_day("23:59").unwrap(), time(23, 59));
+        assert_eq!(parse_time_of_day("7:05").unwrap(), time(7, 5));
+    }
+
+    #[test]
+    fn test_malformed_time_of_day() {
+        for value in [
+            "", "07", "07:", ":30", "24:00", "12:60", "+7:00", "ab:cd", "07:00:00",
+        ] {
+            assert!(parse_time_of_day(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_valid_utc_offset() {
+        let offset = |hours, minutes| UtcOffset::from_hms(hours, minutes, 0).unwrap();
+
+        assert_eq!(parse_utc_offset("+02:00").unwrap(), offset(2, 0));
+        assert_eq!(parse_utc_offset("-03:30").unwrap(), offset(-3, -30));
+        assert_eq!(parse_utc_offset("+00:00").unwrap(), UtcOffset::UTC);
+    }
+
+    #[test]
+    fn test_malformed_utc_offset() {
+        for value in [
+            "", "02:00", "+", "+02", "+24:00", "+02:60", "+200:00", "-128:00", "+-5:00", "+05:-30",
+        ] {
+            assert!(parse_utc_offset(value).is_err(), "{value}");
+        }
+    }
+}
@@ -6,10 +6,11 @@
 use std::thread::sleep;
 use std::time::Duration;
 
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Result, bail};
 use serde::{Deserialize, Serialize};
-use ureq::{Agent, Error};
+use ureq::Agent;
 
+use crate::api::{ApiError, check_response};
 use crate::http::build_agent;
 
 pub(crate) fn register(
@@ -19,9 +20,20 @@ pub(crate) fn register(
     disable_tls_verification: bool,
 ) -> Result<()> {
     let api_client = ClientRegistrationApiClient::new(base_url, disable_tls_verification);
+    let sleep_duration = Duration::from_secs(10);
+
+    register_and_await_approval(&api_client, button_count, audio_output, sleep_duration)
+}
 
+/// Request registration, then poll its status until the registration
+/// has been approved or rejected.
+fn register_and_await_approval(
+    api_client: &ClientRegistrationApiClient,
+    button_count: u8,
+    audio_output: bool,
+    sleep_duration: Duration,
+) -> Result<()> {
     let registration_response = api_client.register(button_count, audio_output)?;
-    let sleep_duration = Duration::from_secs(10);
 
     loop {
         let status_response =
@@ -93,29 +105,63 @@ impl ClientRegistrationApiClient {
     fn register(&self, button_count: u8, audio_output: bool) -> Result<ClientRegistrationResponse> {
         let url = format!("{}/client/register", self.base_url);
 
-        match self.agent.post(&url).send_json(ClientRegistrationRequest {
-            button_count,
-            audio_output,
-        }) {
-            Ok(mut response) => response
-                .body_mut()
-                .read_json::<ClientRegistrationResponse>()
-                .map_err(|e| anyhow!("JSON error: {}", e)),
-            Err(Error::StatusCode(code)) => Err(anyhow!("API error: {}", code)),
-            Err(e) => Err(anyhow!("Network error: {}", e)),
-        }
+        let response =
+            check_response(self.agent.post(&url).send_json(ClientRegistrationRequest {
+                button_count,
+                audio_output,
+            }))?
+            .body_mut()
+            .read_json::<ClientRegistrationResponse>()
+            .map_err(ApiError::InvalidResponse)?;
+        Ok(response)
     }
 
     fn get_registration_status(&self, client_id: &str) -> Result<ClientRegistrationStatusResponse> {
         let url = format!("{}/client/registration_status/{}", self.base_url, client_id);
 
-        match self.agent.get(&url).call() {
-            Ok(mut response) => response
-                .body_mut()
-                .read_json::<ClientRegistrationStatusResponse>()
-                .map_err(|e| anyhow!("JSON error: {}", e)),
-            Err(Error::StatusCode(code)) => Err(anyhow!("API error: {}", code)),
-            Err(e) => Err(anyhow!("Network error: {}", e)),
-        }
+        let response = check_response(self.agent.get(&url).call())?
+            .body_mut()
+            .read_json::<ClientRegistrationStatusResponse>()
+            .map_err(ApiError::InvalidResponse)?;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mockapi::serve_in_background;
+
+    fn register_with(scenario: &str) -> Result<()> {
+        let base_url = serve_in_background(scenario);
+        let api_client = ClientRegistrationApiClient::new(&base_url, false);
+        register_and_await_approval(&api_client, 3, true, Duration::from_millis(10))
+    }
+
+    #[test]
+    fn test_approved_after_pending_polls() {
+        let result = register_with(
+            r#"
+            [registration]
+            pending_polls = 2
+            outcome = "approved"
+            "#,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_rejected_after_pending_polls() {
+        let result = register_with(
+            r#"
+            [registration]
+            pending_polls = 2
+            outcome = "rejected"
+            "#,
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Client registration was rejected."
+        );
     }
 }
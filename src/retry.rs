@@ -0,0 +1,201 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use nanorand::{Rng, WyRand};
+
+use crate::api::ApiError;
+use crate::config::RetryConfig;
+
+/// Repeats operations that failed for transient reasons, waiting an
+/// exponentially growing (and, optionally, randomized) time in between.
+pub(crate) struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Duration,
+    jitter: bool,
+    rng: Mutex<WyRand>,
+}
+
+impl RetryPolicy {
+    pub(crate) fn new(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts.max(1),
+            initial_backoff: Duration::from_millis(config.initial_backoff_in_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_in_ms),
+            deadline: Duration::from_millis(config.deadline_in_ms),
+            jitter: config.jitter,
+            rng: Mutex::new(WyRand::new()),
+        }
+    }
+
+    /// Run the operation until it succeeds, fails permanently, the
+    /// maximum number of attempts has been made, or the deadline has
+    /// passed. The operation is passed the time left until the deadline
+    /// so that an attempt in flight does not outlast it.
+    pub(crate) fn run<T>(
+        &self,
+        description: &str,
+        mut operation: impl FnMut(Duration) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let started_at = Instant::now();
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+
+        loop {
+            let remaining = self.deadline.saturating_sub(started_at.elapsed());
+            let error = match operation(remaining) {
+                Ok(value) => return Ok(value),
+                Err(e) if e.is_transient() => e,
+                Err(e) => return Err(e),
+            };
+
+            if attempt >= self.max_attempts {
+                return Err(error);
+            }
+
+            let delay = self.apply_jitter(backoff);
+            if started_at.elapsed() + delay >= self.deadline {
+                return Err(error);
+            }
+
+            log::debug!(
+                "Attempt {} to {} failed, retrying in {:?}.\n{}",
+                attempt,
+                description,
+                delay,
+                error
+            );
+            sleep(delay);
+
+            backoff = self.next_backoff(backoff);
+            attempt += 1;
+        }
+    }
+
+    fn next_backoff(&self, backoff: Duration) -> Duration {
+        (backoff * 2).min(self.max_backoff)
+    }
+
+    /// Randomize the delay to between half of and the full backoff so
+    /// that clients do not retry in lockstep.
+    fn apply_jitter(&self, backoff: Duration) -> Duration {
+        if !self.jitter {
+            return backoff;
+        }
+
+        let half = backoff / 2;
+        let max_extra = half.as_millis() as u64;
+        let extra = self.rng.lock().unwrap().generate_range(0..=max_extra);
+        half + Duration::from_millis(extra)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ErrorDetails;
+
+    fn policy(max_attempts: u32, deadline_in_ms: u64, jitter: bool) -> RetryPolicy {
+        RetryPolicy::new(&RetryConfig {
+            max_attempts,
+            initial_backoff_in_ms: 10,
+            max_backoff_in_ms: 40,
+            deadline_in_ms,
+            jitter,
+        })
+    }
+
+    fn server_error() -> ApiError {
+        ApiError::ServerError(ErrorDetails {
+            status: 503,
+            message: None,
+        })
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_maximum() {
+        let policy = policy(10, 10000, false);
+
+        let mut backoff = policy.initial_backoff;
+        let mut backoffs = vec![backoff];
+        for _ in 0..4 {
+            backoff = policy.next_backoff(backoff);
+            backoffs.push(backoff);
+        }
+
+        let expected = [10, 20, 40, 40, 40].map(Duration::from_millis);
+        assert_eq!(backoffs, expected);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_half_and_full_backoff() {
+        let backoff = Duration::from_millis(1000);
+
+        let policy = policy(10, 10000, true);
+        for _ in 0..1000 {
+            let delay = policy.apply_jitter(backoff);
+            assert!(delay >= backoff / 2 && delay <= backoff, "{delay:?}");
+        }
+
+        let policy = self::policy(10, 10000, false);
+        assert_eq!(policy.apply_jitter(backoff), backoff);
+    }
+
+    #[test]
+    fn test_transient_errors_are_retried_up_to_maximum_attempts() {
+        let policy = policy(3, 10000, false);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = policy.run("test", |_| {
+            attempts += 1;
+            Err(server_error())
+        });
+
+        assert!(matches!(result, Err(ApiError::ServerError(_))));
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_permanent_errors_are_not_retried() {
+        let policy = policy(3, 10000, false);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = policy.run("test", |_| {
+            attempts += 1;
+            Err(ApiError::UnprocessableEntity(ErrorDetails {
+                status: 422,
+                message: None,
+            }))
+        });
+
+        assert!(matches!(result, Err(ApiError::UnprocessableEntity(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_deadline_bounds_total_time() {
+        let deadline = Duration::from_millis(100);
+        let policy = policy(1000, deadline.as_millis() as u64, false);
+
+        let started_at = Instant::now();
+        let mut remainders = Vec::new();
+        let result: Result<(), _> = policy.run("test", |remaining| {
+            remainders.push(remaining);
+            // Simulate a request that takes up all the time it is given.
+            sleep(remaining.min(Duration::from_millis(30)));
+            Err(server_error())
+        });
+
+        assert!(result.is_err());
+        assert!(started_at.elapsed() < deadline + Duration::from_millis(50));
+        assert!(remainders[0] <= deadline);
+        assert!(remainders.windows(2).all(|pair| pair[1] < pair[0]));
+    }
+}
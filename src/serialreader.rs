@@ -0,0 +1,435 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::thread::{self, sleep};
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use flume::Sender;
+use nix::fcntl::OFlag;
+use nix::sys::termios::{self, BaudRate, ControlFlags, SetArg, SpecialCharacterIndices};
+use serde::Deserialize;
+
+use crate::config::ReaderConfig;
+use crate::devices::{self, RECONNECT_INTERVAL};
+use crate::events::{Event, InputDevice};
+use crate::tagreader::TagReadDispatcher;
+
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+
+/// Longest line accepted with line framing; longer ones are discarded.
+const MAX_LINE_LENGTH: usize = 256;
+
+/// Framing formats spoken by serial RFID readers
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SerialFraming {
+    /// STX, 10 hex digits of data, 2 hex digits of XOR checksum, ETX
+    /// (with optional CR/LF before ETX). Used by RDM6300 and ID-12/ID-20
+    /// compatible readers.
+    #[default]
+    Rdm6300,
+
+    /// ASCII identifier terminated by CR and/or LF, without checksum
+    Line,
+}
+
+pub(crate) fn handle_tag_reads(
+    device_name: String,
+    reader_config: &ReaderConfig,
+    sender: Sender<Event>,
+) -> Result<()> {
+    let port = open_port(&device_name, reader_config.baud_rate)?;
+    log::info!("Opened serial reader device \"{}\".", device_name);
+
+    let dispatcher = TagReadDispatcher::new(reader_config, sender.clone());
+
+    let tag_read_handler = SerialTagReadHandler::new(
+        device_name,
+        reader_config.baud_rate,
+        reader_config.framing,
+        dispatcher,
+        sender,
+    );
+    thread::spawn(move || tag_read_handler.run(port));
+    Ok(())
+}
+
+fn open_port(path: &str, baud_rate: u32) -> Result<File> {
+    let port = OpenOptions::new()
+        .read(true)
+        .custom_flags(OFlag::O_NOCTTY.bits())
+        .open(path)
+        .with_context(|| format!("Could not open serial reader device {}", path))?;
+
+    configure_port(&port, path, baud_rate)?;
+
+    Ok(port)
+}
+
+/// Put the port into raw mode, returning each byte as soon as it arrives.
+fn configure_port(port: &File, path: &str, baud_rate: u32) -> Result<()> {
+    let mut settings = termios::tcgetattr(port)
+        .with_context(|| format!("Could not get terminal attributes of {}", path))?;
+    termios::cfmakeraw(&mut settings);
+    termios::cfsetspeed(&mut settings, to_baud_rate(baud_rate)?)?;
+    settings.control_flags |= ControlFlags::CLOCAL | ControlFlags::CREAD;
+    settings.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+    settings.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+    termios::tcsetattr(port, SetArg::TCSANOW, &settings)
+        .with_context(|| format!("Could not set terminal attributes of {}", path))?;
+
+    Ok(())
+}
+
+fn to_baud_rate(value: u32) -> Result<BaudRate> {
+    match value {
+        1200 => Ok(BaudRate::B1200),
+        2400 => Ok(BaudRate::B2400),
+        4800 => Ok(BaudRate::B4800),
+        9600 => Ok(BaudRate::B9600),
+        19200 => Ok(BaudRate::B19200),
+        38400 => Ok(BaudRate::B38400),
+        57600 => Ok(BaudRate::B57600),
+        115200 => Ok(BaudRate::B115200),
+        _ => bail!("Unsupported baud rate {}", value),
+    }
+}
+
+struct SerialTagReadHandler {
+    device_name: String,
+    baud_rate: u32,
+    framing: SerialFraming,
+    dispatcher: TagReadDispatcher,
+    sender: Sender<Event>,
+}
+
+impl SerialTagReadHandler {
+    fn new(
+        device_name: String,
+        baud_rate: u32,
+        framing: SerialFraming,
+        dispatcher: TagReadDispatcher,
+        sender: Sender<Event>,
+    ) -> Self {
+        Self {
+            device_name,
+            baud_rate,
+            framing,
+            dispatcher,
+            sender,
+        }
+    }
+
+    fn run(mut self, mut port: File) -> Result<()> {
+        loop {
+            let error = self.read_tags(&mut port)?;
+            port = self.reconnect(port, error)?;
+        }
+    }
+
+    /// Read tags until the port fails, then return that error.
+    fn read_tags(&mut self, port: &mut File) -> Result<io::Error> {
+        let mut frame_parser = FrameParser::new(self.framing);
+        let mut buffer = [0u8; 64];
+        loop {
+            let n = match port.read(&mut buffer) {
+                Ok(0) => {
+                    return Ok(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "device was closed",
+                    ));
+                }
+                Ok(n) => n,
+                Err(e) if devices::is_interruption(&e) => continue,
+                Err(e) => return Ok(e),
+            };
+
+            for byte in &buffer[..n] {
+                match frame_parser.push(*byte) {
+                    Some(Ok(value)) => self.dispatcher.dispatch(&value)?,
+                    Some(Err(e)) => log::warn!("Discarding invalid frame from serial reader: {e}"),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Close the failed port, report the disconnection, then wait for the
+    /// port to reappear and reopen it.
+    fn reconnect(&self, port: File, error: io::Error) -> Result<File> {
+        drop(port);
+
+        log::warn!("Lost connection to serial reader device: {}", error);
+        self.sender.send(Event::InputDeviceDisconnected {
+            device: InputDevice::Reader,
+        })?;
+
+        loop {
+            sleep(RECONNECT_INTERVAL);
+
+            match open_port(&self.device_name, self.baud_rate) {
+                Ok(port) => {
+                    log::info!("Reconnected serial reader device.");
+                    self.sender.send(Event::InputDeviceReconnected {
+                        device: InputDevice::Reader,
+                    })?;
+                    return Ok(port);
+                }
+                Err(e) => log::debug!("Could not reopen serial reader device: {e}"),
+            }
+        }
+    }
+}
+
+struct FrameParser {
+    framing: SerialFraming,
+    buffer: Vec<u8>,
+    in_frame: bool,
+    /// Skip the rest of an overlong line.
+    discarding_line: bool,
+}
+
+impl FrameParser {
+    fn new(framing: SerialFraming) -> Self {
+        Self {
+            framing,
+            buffer: Vec::new(),
+            in_frame: false,
+            discarding_line: false,
+        }
+    }
+
+    /// Feed a byte, returning a tag once a complete frame has been read.
+    fn push(&mut self, byte: u8) -> Option<Result<String>> {
+        match self.framing {
+            SerialFraming::Rdm6300 => self.push_rdm6300(byte),
+            SerialFraming::Line => self.push_line(byte),
+        }
+    }
+
+    fn push_rdm6300(&mut self, byte: u8) -> Option<Result<String>> {
+        match byte {
+            STX => {
+                self.buffer.clear();
+                self.in_frame = true;
+                None
+            }
+            ETX if self.in_frame => {
+                self.in_frame = false;
+                Some(parse_rdm6300_frame(&self.buffer))
+            }
+            CR | LF => None,
+            _ if self.in_frame => {
+                self.buffer.push(byte);
+                if self.buffer.len() > 12 {
+                    self.in_frame = false;
+                    return Some(Err(anyhow!("Frame too long")));
+                }
+                None
+            }
+            _ => None, // Ignore noise between frames.
+        }
+    }
+
+    fn push_line(&mut self, byte: u8) -> Option<Result<String>> {
+        match byte {
+            CR | LF => {
+                self.discarding_line = false;
+                if self.buffer.is_empty() {
+                    return None;
+                }
+                let line = String::from_utf8(std::mem::take(&mut self.buffer))
+                    .map(|line| line.trim().to_string())
+                    .context("Line is not valid UTF-8");
+                Some(line)
+            }
+            _ if self.discarding_line => None,
+            _ => {
+                self.buffer.push(byte);
+                if self.buffer.len() > MAX_LINE_LENGTH {
+                    self.buffer.clear();
+                    self.discarding_line = true;
+                    return Some(Err(anyhow!("Line too long")));
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Validate the checksum and return the card number as zero-padded,
+/// 10-digit decimal number (as printed by keyboard-emulating readers).
+fn parse_rdm6300_frame(frame: &[u8]) -> Result<String> {
+    ensure!(
+        frame.len() == 12,
+        "Expected 12 characters, got {}",
+        frame.len()
+    );
+
+    ensure!(frame.is_ascii(), "Frame is not ASCII");
+    let text = String::from_utf8_lossy(frame);
+    let bytes = (0..6)
+        .map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .context("Frame contains non-hex characters")?;
+
+    let (data, checksum) = bytes.split_at(5);
+    let expected_checksum = data.iter().fold(0, |acc, b| acc ^ b);
+    ensure!(
+        checksum[0] == expected_checksum,
+        "Checksum mismatch (expected {:02X}, got {:02X})",
+        expected_checksum,
+        checksum[0]
+    );
+
+    let card_number = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+    Ok(format!("{:010}", card_number))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::Duration;
+
+    use nix::pty;
+
+    use super::*;
+
+    /// Card number 0x00A1B2C3 with version byte 0x0F and checksum 0xDF
+    const FRAME: &[u8] = b"\x020F00A1B2C3DF\x03";
+
+    fn push_all(parser: &mut FrameParser, bytes: &[u8]) -> Vec<Result<String>> {
+        bytes.iter().filter_map(|byte| parser.push(*byte)).collect()
+    }
+
+    #[test]
+    fn test_parse_rdm6300_frame() {
+        assert_eq!(parse_rdm6300_frame(b"0F00A1B2C3DF").unwrap(), "0010597059");
+    }
+
+    #[test]
+    fn test_parse_rdm6300_frame_rejects_bad_checksum() {
+        let e = parse_rdm6300_frame(b"0F00A1B2C3DE").unwrap_err();
+        assert!(e.to_string().contains("Checksum mismatch"));
+    }
+
+    #[test]
+    fn test_parse_rdm6300_frame_rejects_non_hex_data() {
+        assert!(parse_rdm6300_frame(b"0F00A1B2CXDF").is_err());
+    }
+
+    #[test]
+    fn test_parse_rdm6300_frame_rejects_wrong_length() {
+        assert!(parse_rdm6300_frame(b"0F00A1B2C3").is_err());
+        assert!(parse_rdm6300_frame(b"0F00A1B2C3DF00").is_err());
+    }
+
+    #[test]
+    fn test_rdm6300_framing() {
+        let mut parser = FrameParser::new(SerialFraming::Rdm6300);
+        let results = push_all(&mut parser, FRAME);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap(), "0010597059");
+    }
+
+    #[test]
+    fn test_rdm6300_framing_accepts_cr_lf_before_etx() {
+        let mut parser = FrameParser::new(SerialFraming::Rdm6300);
+        let results = push_all(&mut parser, b"\x020F00A1B2C3DF\r\n\x03");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap(), "0010597059");
+    }
+
+    #[test]
+    fn test_rdm6300_framing_ignores_noise_between_frames() {
+        let mut parser = FrameParser::new(SerialFraming::Rdm6300);
+        let mut bytes = b"noise\x03".to_vec();
+        bytes.extend_from_slice(FRAME);
+        bytes.extend_from_slice(b"\xffmore noise");
+        bytes.extend_from_slice(FRAME);
+
+        let results = push_all(&mut parser, &bytes);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+
+    #[test]
+    fn test_rdm6300_framing_rejects_too_long_frame() {
+        let mut parser = FrameParser::new(SerialFraming::Rdm6300);
+        let results = push_all(&mut parser, b"\x020F00A1B2C3DF00\x03");
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+
+        // Parser recovers with the next frame.
+        let results = push_all(&mut parser, FRAME);
+        assert_eq!(results[0].as_ref().unwrap(), "0010597059");
+    }
+
+    #[test]
+    fn test_line_framing() {
+        let mut parser = FrameParser::new(SerialFraming::Line);
+        let results = push_all(&mut parser, b"1234\r\n\r\nABCD \n");
+        let lines: Vec<&str> = results
+            .iter()
+            .map(|r| r.as_ref().unwrap().as_str())
+            .collect();
+        assert_eq!(lines, ["1234", "ABCD"]);
+    }
+
+    #[test]
+    fn test_line_framing_discards_overlong_line() {
+        let mut parser = FrameParser::new(SerialFraming::Line);
+        let mut bytes = vec![b'1'; MAX_LINE_LENGTH * 3];
+        bytes.extend_from_slice(b"\n1234\n");
+
+        let results = push_all(&mut parser, &bytes);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap(), "1234");
+        assert!(parser.buffer.is_empty());
+    }
+
+    #[test]
+    fn test_handler_reads_tags_from_pseudo_terminal() {
+        let pty = pty::openpty(None, None).unwrap();
+        let port = File::from(pty.slave);
+        configure_port(&port, "pseudo-terminal", 9600).unwrap();
+        let mut master = File::from(pty.master);
+
+        let (sender, receiver) = flume::unbounded();
+        let dispatcher = TagReadDispatcher::new(&ReaderConfig::default(), sender.clone());
+        let handler = SerialTagReadHandler::new(
+            "/nonexistent".to_string(),
+            9600,
+            SerialFraming::Rdm6300,
+            dispatcher,
+            sender,
+        );
+        thread::spawn(move || handler.run(port));
+
+        master.write_all(FRAME).unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::TagRead {
+                tag: "0010597059".to_string()
+            }
+        );
+
+        // Closing the other end makes reads fail.
+        drop(master);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::InputDeviceDisconnected {
+                device: InputDevice::Reader
+            }
+        );
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use flume::Sender;
+
+use crate::buttons::{Button, ButtonPress};
+use crate::events::{Event, InputDevice};
+
+/// Read simulated input commands from a script file (or, if none is
+/// given, from standard input) and send the corresponding events.
+///
+/// Supported commands (one per line; empty lines and lines starting
+/// with `#` are ignored):
+///
+/// - `tag <identifier>`
+/// - `button <number> [short|long|double]`
+/// - `disconnect reader|buttons`
+/// - `reconnect reader|buttons`
+/// - `wait <milliseconds>`
+/// - `quit`
+///
+/// Shutdown is requested once the end of the input has been reached.
+pub(crate) fn handle_simulated_input(
+    script_filename: Option<PathBuf>,
+    sender: Sender<Event>,
+) -> Result<()> {
+    let input: Box<dyn BufRead + Send> = match script_filename {
+        Some(filename) => {
+            let file = File::open(&filename).with_context(|| {
+                format!("Could not open simulation script {}", filename.display())
+            })?;
+            log::info!("Simulating input from script {}.", filename.display());
+            Box::new(BufReader::new(file))
+        }
+        None => {
+            log::info!("Simulating input from standard input.");
+            Box::new(BufReader::new(io::stdin()))
+        }
+    };
+
+    thread::spawn(move || run(input, sender));
+    Ok(())
+}
+
+fn run(input: Box<dyn BufRead + Send>, sender: Sender<Event>) -> Result<()> {
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match parse_command(line) {
+            Ok(Command::Send(event)) => sender.send(event)?,
+            Ok(Command::Wait(duration)) => sleep(duration),
+            Ok(Command::Quit) => break,
+            Err(e) => log::warn!("Ignoring invalid command in line {}: {e}", index + 1),
+        }
+    }
+
+    sender.send(Event::ShutdownRequested)?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Send(Event),
+    Wait(Duration),
+    Quit,
+}
+
+pub(crate) fn parse_command(line: &str) -> Result<Command> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+    match args.as_slice() {
+        ["tag", tag] => Ok(Command::Send(Event::TagRead {
+            tag: tag.to_string(),
+        })),
+        ["button", number] => parse_button_command(number, "short"),
+        ["button", number, press] => parse_button_command(number, press),
+        ["disconnect", device] => Ok(Command::Send(Event::InputDeviceDisconnected {
+            device: parse_input_device(device)?,
+        })),
+        ["reconnect", device] => Ok(Command::Send(Event::InputDeviceReconnected {
+            device: parse_input_device(device)?,
+        })),
+        ["wait", milliseconds] => {
+            let milliseconds = milliseconds
+                .parse()
+                .with_context(|| format!("Invalid duration '{}'", milliseconds))?;
+            Ok(Command::Wait(Duration::from_millis(milliseconds)))
+        }
+        ["quit"] => Ok(Command::Quit),
+        _ => bail!("Unknown command '{}'", line),
+    }
+}
+
+fn parse_button_command(number: &str, press: &str) -> Result<Command> {
+    let button = Button::try_from(format!("button{}", number)).map_err(|e| anyhow!(e))?;
+    let press = match press {
+        "short" => ButtonPress::Short,
+        "long" => ButtonPress::Long,
+        "double" => ButtonPress::Double,
+        _ => bail!("Invalid button press '{}'", press),
+    };
+    Ok(Command::Send(Event::ButtonPressed { button, press }))
+}
+
+fn parse_input_device(name: &str) -> Result<InputDevice> {
+    match name {
+        "reader" => Ok(InputDevice::Reader),
+        "buttons" => Ok(InputDevice::Buttons),
+        _ => bail!("Invalid input device '{}'", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn button(name: &str) -> Button {
+        Button::try_from(name.to_string()).unwrap()
+    }
+
+    fn error_message(line: &str) -> String {
+        parse_command(line).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse_tag_command() {
+        assert_eq!(
+            parse_command("tag 0012345678").unwrap(),
+            Command::Send(Event::TagRead {
+                tag: "0012345678".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_button_commands() {
+        for (line, name, press) in [
+            ("button 1", "button1", ButtonPress::Short),
+            ("button 2 short", "button2", ButtonPress::Short),
+            ("button 3 long", "button3", ButtonPress::Long),
+            ("button 12 double", "button12", ButtonPress::Double),
+        ] {
+            assert_eq!(
+                parse_command(line).unwrap(),
+                Command::Send(Event::ButtonPressed {
+                    button: button(name),
+                    press
+                }),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_device_wait_and_quit_commands() {
+        assert_eq!(
+            parse_command("disconnect reader").unwrap(),
+            Command::Send(Event::InputDeviceDisconnected {
+                device: InputDevice::Reader
+            })
+        );
+        assert_eq!(
+            parse_command("reconnect buttons").unwrap(),
+            Command::Send(Event::InputDeviceReconnected {
+                device: InputDevice::Buttons
+            })
+        );
+        assert_eq!(
+            parse_command("wait 250").unwrap(),
+            Command::Wait(Duration::from_millis(250))
+        );
+        assert_eq!(parse_command("quit").unwrap(), Command::Quit);
+    }
+
+    #[test]
+    fn test_parse_unknown_command() {
+        assert_eq!(error_message("jump 3"), "Unknown command 'jump 3'");
+    }
+
+    #[test]
+    fn test_parse_missing_arguments() {
+        for line in ["tag", "button", "disconnect", "wait"] {
+            assert_eq!(error_message(line), format!("Unknown command '{line}'"));
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_arguments() {
+        assert_eq!(
+            error_message("button 1 triple"),
+            "Invalid button press 'triple'"
+        );
+        assert_eq!(
+            error_message("disconnect mouse"),
+            "Invalid input device 'mouse'"
+        );
+        assert_eq!(error_message("wait soon"), "Invalid duration 'soon'");
+        assert!(parse_command("button 0").is_err());
+        assert!(parse_command("button x").is_err());
+    }
+
+    #[test]
+    fn test_run_sends_events_and_requests_shutdown() {
+        let script = "# comment\n\ntag 0012345678\nbogus\nbutton 2 long\nquit\ntag 0099999999\n";
+        let (sender, receiver) = flume::unbounded();
+
+        run(Box::new(Cursor::new(script)), sender).unwrap();
+
+        let events: Vec<Event> = receiver.drain().collect();
+        assert_eq!(
+            events,
+            [
+                Event::TagRead {
+                    tag: "0012345678".to_string()
+                },
+                Event::ButtonPressed {
+                    button: button("button2"),
+                    press: ButtonPress::Long
+                },
+                Event::ShutdownRequested,
+            ]
+        );
+    }
+}
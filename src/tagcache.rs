@@ -0,0 +1,193 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::api::TagDetails;
+
+/// Persistent cache of tag details so tags can be resolved locally when
+/// the API is slow or unavailable.
+pub(crate) struct TagCache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: HashMap<String, CacheEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    details: TagDetails,
+    #[serde(with = "time::serde::rfc3339")]
+    fetched_at: OffsetDateTime,
+}
+
+impl CacheEntry {
+    fn new(details: TagDetails) -> Self {
+        Self {
+            details,
+            fetched_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        OffsetDateTime::now_utc() - self.fetched_at < ttl
+    }
+}
+
+impl TagCache {
+    pub(crate) fn load(path: PathBuf, ttl: Duration) -> Result<Self> {
+        let entries = if path.exists() {
+            let file = File::open(&path)
+                .with_context(|| format!("Could not open tag cache file {}", path.display()))?;
+            serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Invalid tag cache file {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self { path, ttl, entries })
+    }
+
+    /// Return details for the tag unless missing or expired.
+    pub(crate) fn get(&self, tag: &str) -> Option<&TagDetails> {
+        self.entries
+            .get(tag)
+            .filter(|entry| entry.is_fresh(self.ttl))
+            .map(|entry| &entry.details)
+    }
+
+    /// Store the details the API returned for the tag, or remove the tag
+    /// if the API does not know it (anymore).
+    pub(crate) fn update(&mut self, tag: &str, details: Option<&TagDetails>) -> Result<()> {
+        match details {
+            Some(details) => self.insert(tag, details.clone()),
+            None => self.remove(tag),
+        }
+    }
+
+    fn insert(&mut self, tag: &str, details: TagDetails) -> Result<()> {
+        self.entries
+            .insert(tag.to_string(), CacheEntry::new(details));
+        self.write()
+    }
+
+    pub(crate) fn insert_all(&mut self, all_details: Vec<TagDetails>) -> Result<()> {
+        for details in all_details {
+            self.entries
+                .insert(details.identifier.clone(), CacheEntry::new(details));
+        }
+        self.write()
+    }
+
+    fn remove(&mut self, tag: &str) -> Result<()> {
+        if self.entries.remove(tag).is_some() {
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn write(&self) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &self.entries)?;
+        writer.flush()?;
+        writer.into_inner()?.sync_all()?;
+
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::TagUser;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(3600);
+
+    fn details(identifier: &str, user_id: &str) -> TagDetails {
+        TagDetails {
+            identifier: identifier.to_string(),
+            user: TagUser {
+                id: user_id.to_string(),
+                screen_name: None,
+            },
+            sound_name: None,
+        }
+    }
+
+    #[test]
+    fn test_get_returns_fresh_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = TagCache::load(dir.path().join("cache.json"), TTL).unwrap();
+
+        cache
+            .update("0001", Some(&details("0001", "alice")))
+            .unwrap();
+
+        assert_eq!(cache.get("0001").unwrap().user.id, "alice");
+        assert!(cache.get("0002").is_none());
+    }
+
+    #[test]
+    fn test_get_ignores_expired_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = TagCache::load(dir.path().join("cache.json"), TTL).unwrap();
+
+        cache
+            .update("0001", Some(&details("0001", "alice")))
+            .unwrap();
+        cache.entries.get_mut("0001").unwrap().fetched_at -= TTL;
+
+        assert!(cache.get("0001").is_none());
+    }
+
+    #[test]
+    fn test_entries_persist_across_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+
+        let mut cache = TagCache::load(path.clone(), TTL).unwrap();
+        cache
+            .update("0001", Some(&details("0001", "alice")))
+            .unwrap();
+        cache
+            .insert_all(vec![details("0002", "bob"), details("0003", "carol")])
+            .unwrap();
+
+        let cache = TagCache::load(path, TTL).unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get("0001").unwrap().user.id, "alice");
+        assert_eq!(cache.get("0003").unwrap().user.id, "carol");
+    }
+
+    #[test]
+    fn test_update_without_details_removes_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+
+        let mut cache = TagCache::load(path.clone(), TTL).unwrap();
+        cache
+            .update("0001", Some(&details("0001", "alice")))
+            .unwrap();
+        cache.update("0001", None).unwrap();
+
+        assert!(cache.get("0001").is_none());
+        assert_eq!(TagCache::load(path, TTL).unwrap().len(), 0);
+    }
+}
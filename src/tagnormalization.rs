@@ -0,0 +1,258 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use anyhow::{Context, Result, ensure};
+use serde::Deserialize;
+
+/// A single transformation of a tag identifier
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub(crate) enum NormalizationStep {
+    StripPrefix {
+        prefix: String,
+    },
+    StripSuffix {
+        suffix: String,
+    },
+    /// Remove a fixed number of characters from the start (e.g. a
+    /// facility code that varies between cards).
+    DropLeading {
+        count: usize,
+    },
+    DropTrailing {
+        count: usize,
+    },
+    DecimalToHex,
+    HexToDecimal,
+    ReverseBytes,
+    ZeroPad {
+        width: usize,
+    },
+    Lowercase,
+    Uppercase,
+}
+
+/// Transforms tag identifiers as output by a specific reader model into
+/// a canonical form by applying the configured steps in order.
+pub(crate) struct TagNormalizer {
+    steps: Vec<NormalizationStep>,
+}
+
+impl TagNormalizer {
+    pub(crate) fn new(steps: Vec<NormalizationStep>) -> Self {
+        Self { steps }
+    }
+
+    pub(crate) fn normalize(&self, tag: &str) -> Result<String> {
+        self.steps.iter().try_fold(tag.to_string(), |value, step| {
+            apply_step(step, &value)
+                .with_context(|| format!("Could not apply step {:?} to '{}'", step, value))
+        })
+    }
+}
+
+fn apply_step(step: &NormalizationStep, value: &str) -> Result<String> {
+    match step {
+        NormalizationStep::StripPrefix { prefix } => Ok(value
+            .strip_prefix(prefix.as_str())
+            .unwrap_or(value)
+            .to_string()),
+        NormalizationStep::StripSuffix { suffix } => Ok(value
+            .strip_suffix(suffix.as_str())
+            .unwrap_or(value)
+            .to_string()),
+        NormalizationStep::DropLeading { count } => {
+            let length = value.chars().count();
+            ensure!(length > *count, "Value too short");
+            Ok(value.chars().skip(*count).collect())
+        }
+        NormalizationStep::DropTrailing { count } => {
+            let length = value.chars().count();
+            ensure!(length > *count, "Value too short");
+            Ok(value.chars().take(length - count).collect())
+        }
+        NormalizationStep::DecimalToHex => {
+            let number = value.parse::<u128>()?;
+            let hex = format!("{:X}", number);
+            Ok(pad_to_even_length(&hex))
+        }
+        NormalizationStep::HexToDecimal => {
+            let number = u128::from_str_radix(value, 16)?;
+            Ok(number.to_string())
+        }
+        NormalizationStep::ReverseBytes => {
+            ensure!(
+                value.chars().all(|ch| ch.is_ascii_hexdigit()),
+                "Not a hexadecimal value"
+            );
+            let hex = pad_to_even_length(value);
+            let reversed = hex
+                .as_bytes()
+                .chunks(2)
+                .rev()
+                .map(|pair| String::from_utf8_lossy(pair).into_owned())
+                .collect();
+            Ok(reversed)
+        }
+        NormalizationStep::ZeroPad { width } => Ok(format!("{:0>width$}", value, width = width)),
+        NormalizationStep::Lowercase => Ok(value.to_lowercase()),
+        NormalizationStep::Uppercase => Ok(value.to_uppercase()),
+    }
+}
+
+fn pad_to_even_length(hex: &str) -> String {
+    if hex.len() % 2 == 0 {
+        hex.to_string()
+    } else {
+        format!("0{}", hex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(steps: Vec<NormalizationStep>, tag: &str) -> Result<String> {
+        TagNormalizer::new(steps).normalize(tag)
+    }
+
+    fn apply(step: NormalizationStep, value: &str) -> Result<String> {
+        apply_step(&step, value)
+    }
+
+    #[test]
+    fn test_strip_prefix_and_suffix() {
+        let prefix = || NormalizationStep::StripPrefix {
+            prefix: "ID".to_string(),
+        };
+        assert_eq!(apply(prefix(), "ID1234").unwrap(), "1234");
+        assert_eq!(apply(prefix(), "1234").unwrap(), "1234");
+
+        let suffix = NormalizationStep::StripSuffix {
+            suffix: "\r".to_string(),
+        };
+        assert_eq!(apply(suffix, "1234\r").unwrap(), "1234");
+    }
+
+    #[test]
+    fn test_drop_leading_and_trailing() {
+        let leading = |count| NormalizationStep::DropLeading { count };
+        assert_eq!(apply(leading(2), "0F00A1B2C3").unwrap(), "00A1B2C3");
+        assert_eq!(apply(leading(0), "1234").unwrap(), "1234");
+        assert!(apply(leading(4), "1234").is_err());
+
+        let trailing = |count| NormalizationStep::DropTrailing { count };
+        assert_eq!(apply(trailing(2), "00A1B2C3DF").unwrap(), "00A1B2C3");
+        assert!(apply(trailing(5), "1234").is_err());
+    }
+
+    #[test]
+    fn test_decimal_to_hex() {
+        assert_eq!(apply(NormalizationStep::DecimalToHex, "255").unwrap(), "FF");
+        assert_eq!(
+            apply(NormalizationStep::DecimalToHex, "4096").unwrap(),
+            "1000"
+        );
+        assert_eq!(apply(NormalizationStep::DecimalToHex, "10").unwrap(), "0A");
+        assert!(apply(NormalizationStep::DecimalToHex, "12AB").is_err());
+    }
+
+    #[test]
+    fn test_hex_to_decimal() {
+        assert_eq!(
+            apply(NormalizationStep::HexToDecimal, "00A1B2C3").unwrap(),
+            "10597059"
+        );
+        assert_eq!(apply(NormalizationStep::HexToDecimal, "ff").unwrap(), "255");
+        assert!(apply(NormalizationStep::HexToDecimal, "XYZ").is_err());
+        assert!(apply(NormalizationStep::HexToDecimal, "").is_err());
+    }
+
+    #[test]
+    fn test_reverse_bytes() {
+        assert_eq!(
+            apply(NormalizationStep::ReverseBytes, "A1B2C3D4").unwrap(),
+            "D4C3B2A1"
+        );
+        assert_eq!(
+            apply(NormalizationStep::ReverseBytes, "ABC").unwrap(),
+            "BC0A"
+        );
+        assert!(apply(NormalizationStep::ReverseBytes, "A1-B2").is_err());
+    }
+
+    #[test]
+    fn test_zero_pad() {
+        let zero_pad = |width| NormalizationStep::ZeroPad { width };
+        assert_eq!(apply(zero_pad(10), "1234").unwrap(), "0000001234");
+        assert_eq!(apply(zero_pad(2), "1234").unwrap(), "1234");
+    }
+
+    #[test]
+    fn test_change_case() {
+        assert_eq!(apply(NormalizationStep::Lowercase, "A1b2").unwrap(), "a1b2");
+        assert_eq!(apply(NormalizationStep::Uppercase, "A1b2").unwrap(), "A1B2");
+    }
+
+    #[test]
+    fn test_chain_hex_to_decimal_and_zero_pad() {
+        let steps = vec![
+            NormalizationStep::DropLeading { count: 2 },
+            NormalizationStep::HexToDecimal,
+            NormalizationStep::ZeroPad { width: 10 },
+        ];
+        assert_eq!(normalize(steps, "0F00A1B2C3").unwrap(), "0010597059");
+    }
+
+    #[test]
+    fn test_chain_reverse_bytes_and_hex_to_decimal() {
+        let steps = vec![
+            NormalizationStep::Uppercase,
+            NormalizationStep::ReverseBytes,
+            NormalizationStep::HexToDecimal,
+        ];
+        assert_eq!(normalize(steps, "c3b2a100").unwrap(), "10597059");
+    }
+
+    #[test]
+    fn test_chain_reports_failing_step() {
+        let steps = vec![
+            NormalizationStep::HexToDecimal,
+            NormalizationStep::ZeroPad { width: 10 },
+        ];
+        let e = normalize(steps, "not-hex").unwrap_err();
+        assert!(e.to_string().contains("HexToDecimal"));
+    }
+
+    #[test]
+    fn test_no_steps() {
+        assert_eq!(normalize(Vec::new(), "1234").unwrap(), "1234");
+    }
+
+    #[test]
+    fn test_deserialize_steps() {
+        #[derive(Deserialize)]
+        struct Steps {
+            normalization: Vec<NormalizationStep>,
+        }
+
+        let steps: Steps = toml::from_str(
+            r#"
+            [[normalization]]
+            step = "drop_trailing"
+            count = 2
+
+            [[normalization]]
+            step = "zero_pad"
+            width = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            normalize(steps.normalization, "1234DF").unwrap(),
+            "0000001234"
+        );
+    }
+}
@@ -3,46 +3,186 @@
  * License: MIT
  */
 
+use std::collections::HashSet;
+use std::io;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use evdev::{Device, EventSummary, EventType, InputEvent, KeyCode};
+use anyhow::{Context, Result};
+use evdev::{Device, EventSummary, InputEvent, KeyCode};
 use flume::Sender;
 
-use crate::devices;
-use crate::events::Event;
+use crate::config::ReaderConfig;
+use crate::devices::{self, ReconnectingInputDevice};
+use crate::events::{Event, InputDevice};
+use crate::keycodes;
+use crate::keymap::{self, KeyboardLayout};
+use crate::tagnormalization::TagNormalizer;
 
-pub(crate) fn handle_tag_reads(device_name: String, sender: Sender<Event>) -> Result<()> {
-    let device = open_device(device_name)?;
+pub(crate) fn handle_tag_reads(
+    device_name: String,
+    reader_config: &ReaderConfig,
+    sender: Sender<Event>,
+) -> Result<()> {
+    let terminator_keys = parse_terminator_keys(&reader_config.terminator_keys)?;
+
+    let (reconnecting_device, device) = ReconnectingInputDevice::open(
+        device_name,
+        "reader input device".to_string(),
+        InputDevice::Reader,
+    )?;
+
+    let dispatcher = TagReadDispatcher::new(reader_config, sender.clone());
 
-    let tag_read_handler = TagReadHandler::new(sender);
+    let tag_read_handler = TagReadHandler::new(
+        reader_config.keyboard_layout,
+        terminator_keys,
+        dispatcher,
+        reconnecting_device,
+        sender,
+    );
     thread::spawn(move || tag_read_handler.run(device));
     Ok(())
 }
 
-fn open_device(device_name: String) -> Result<Device> {
-    let device_label = "reader input device".to_string();
-    devices::open_input_device(device_name, device_label)
+fn parse_terminator_keys(key_code_names: &[String]) -> Result<HashSet<KeyCode>> {
+    key_code_names
+        .iter()
+        .map(|name| keycodes::parse_key_code_name(name).context("Invalid terminator key"))
+        .collect()
+}
+
+/// Normalizes identifiers read from a tag reader, suppresses
+/// duplicates, and sends the resulting tag read events.
+pub(crate) struct TagReadDispatcher {
+    normalizer: TagNormalizer,
+    duplicate_filter: DuplicateTagFilter,
+    sender: Sender<Event>,
+}
+
+impl TagReadDispatcher {
+    pub(crate) fn new(reader_config: &ReaderConfig, sender: Sender<Event>) -> Self {
+        Self {
+            normalizer: TagNormalizer::new(reader_config.normalization.clone()),
+            duplicate_filter: DuplicateTagFilter::new(
+                Duration::from_millis(reader_config.duplicate_suppression_window_in_ms),
+                reader_config.reannounce_duplicates,
+            ),
+            sender,
+        }
+    }
+
+    pub(crate) fn dispatch(&mut self, value: &str) -> Result<()> {
+        let tag = match self.normalizer.normalize(value) {
+            Ok(tag) => tag,
+            Err(e) => {
+                log::warn!("Discarding tag read: {e:#}");
+                return Ok(());
+            }
+        };
+
+        if !self.duplicate_filter.accept(&tag, Instant::now()) {
+            log::debug!("Ignoring repeated read of tag {tag}.");
+            return Ok(());
+        }
+
+        self.sender.send(Event::TagRead { tag })?;
+        Ok(())
+    }
+}
+
+/// Ignores repeated reads of the same tag within a time window (e.g.
+/// while a card is held against the reader).
+struct DuplicateTagFilter {
+    window: Duration,
+    reannounce: bool,
+    last_tag: Option<String>,
+    last_seen_at: Instant,
+    last_accepted_at: Instant,
+}
+
+impl DuplicateTagFilter {
+    fn new(window: Duration, reannounce: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            window,
+            reannounce,
+            last_tag: None,
+            last_seen_at: now,
+            last_accepted_at: now,
+        }
+    }
+
+    /// Without re-announcement, the window is extended with every repeated
+    /// read, so a tag kept at the reader is reported only once. With
+    /// re-announcement, the tag is reported again once the window since
+    /// it was last reported has passed.
+    fn accept(&mut self, tag: &str, now: Instant) -> bool {
+        let is_repeat = self.last_tag.as_deref() == Some(tag);
+        let reference = if self.reannounce {
+            self.last_accepted_at
+        } else {
+            self.last_seen_at
+        };
+        let is_duplicate = is_repeat && now.duration_since(reference) < self.window;
+
+        self.last_seen_at = now;
+        if !is_duplicate {
+            self.last_tag = Some(tag.to_string());
+            self.last_accepted_at = now;
+        }
+
+        !is_duplicate
+    }
 }
 
 struct TagReadHandler {
+    keyboard_layout: KeyboardLayout,
+    terminator_keys: HashSet<KeyCode>,
+    dispatcher: TagReadDispatcher,
+    reconnecting_device: ReconnectingInputDevice,
     sender: Sender<Event>,
 }
 
 impl TagReadHandler {
-    fn new(sender: Sender<Event>) -> Self {
-        Self { sender }
+    fn new(
+        keyboard_layout: KeyboardLayout,
+        terminator_keys: HashSet<KeyCode>,
+        dispatcher: TagReadDispatcher,
+        reconnecting_device: ReconnectingInputDevice,
+        sender: Sender<Event>,
+    ) -> Self {
+        Self {
+            keyboard_layout,
+            terminator_keys,
+            dispatcher,
+            reconnecting_device,
+            sender,
+        }
     }
 
-    fn run(&self, mut device: Device) -> Result<()> {
-        let mut tag_reader = TagReader::new();
+    fn run(mut self, mut device: Device) -> Result<()> {
         loop {
-            for event in device.fetch_events()? {
+            let error = self.read_tags(&mut device)?;
+            device = self
+                .reconnecting_device
+                .reconnect(device, error, &self.sender)?;
+        }
+    }
+
+    /// Read tags until the device fails, then return that error.
+    fn read_tags(&mut self, device: &mut Device) -> Result<io::Error> {
+        let mut tag_reader = TagReader::new(self.keyboard_layout, self.terminator_keys.clone());
+        loop {
+            let events = match device.fetch_events() {
+                Ok(events) => events,
+                Err(e) if devices::is_interruption(&e) => continue,
+                Err(e) => return Ok(e),
+            };
+
+            for event in events {
                 if let Some(value) = tag_reader.handle_event(event) {
-                    let event = Event::TagRead {
-                        tag: value.to_string(),
-                    };
-                    self.sender.send(event)?;
+                    self.dispatcher.dispatch(&value)?;
                 }
             }
         }
@@ -50,60 +190,201 @@ impl TagReadHandler {
 }
 
 struct TagReader {
+    keyboard_layout: KeyboardLayout,
+    terminator_keys: HashSet<KeyCode>,
+    shift_keys_pressed: HashSet<KeyCode>,
     chars_read: String,
 }
 
 impl TagReader {
-    fn new() -> Self {
+    fn new(keyboard_layout: KeyboardLayout, terminator_keys: HashSet<KeyCode>) -> Self {
         Self {
+            keyboard_layout,
+            terminator_keys,
+            shift_keys_pressed: HashSet::new(),
             chars_read: String::new(),
         }
     }
 
     fn handle_event(&mut self, event: InputEvent) -> Option<String> {
-        if !self.is_key_released(event) {
+        let EventSummary::Key(_, key_code, value) = event.destructure() else {
             return None;
-        }
+        };
 
-        if let EventSummary::Key(_, key_code, 0) = event.destructure() {
-            match key_code {
-                KeyCode::KEY_ENTER => {
-                    let input = &self.chars_read.as_str().to_owned();
+        if keymap::is_shift_key(key_code) {
+            match value {
+                0 => self.shift_keys_pressed.remove(&key_code),
+                _ => self.shift_keys_pressed.insert(key_code),
+            };
+            return None;
+        }
 
-                    self.chars_read.clear();
+        // Evaluate on key press, while the shift state applies.
+        if value != 1 {
+            return None;
+        }
 
-                    Some(input.to_owned())
-                }
-                key_code => match self.get_char(key_code) {
-                    Some(ch) => {
-                        self.chars_read.push(ch);
-                        None
-                    }
-                    None => None,
-                },
+        if self.terminator_keys.contains(&key_code) {
+            // Readers may send several terminators (e.g. Enter and Tab).
+            if self.chars_read.is_empty() {
+                return None;
             }
-        } else {
-            None
+
+            return Some(std::mem::take(&mut self.chars_read));
         }
-    }
 
-    fn is_key_released(&self, event: InputEvent) -> bool {
-        event.event_type() == EventType::KEY && event.value() == 0
+        if let Some(ch) = self.get_char(key_code) {
+            self.chars_read.push(ch);
+        }
+
+        None
     }
 
     fn get_char(&self, key_code: KeyCode) -> Option<char> {
-        match key_code {
-            KeyCode::KEY_1 => Some('1'),
-            KeyCode::KEY_2 => Some('2'),
-            KeyCode::KEY_3 => Some('3'),
-            KeyCode::KEY_4 => Some('4'),
-            KeyCode::KEY_5 => Some('5'),
-            KeyCode::KEY_6 => Some('6'),
-            KeyCode::KEY_7 => Some('7'),
-            KeyCode::KEY_8 => Some('8'),
-            KeyCode::KEY_9 => Some('9'),
-            KeyCode::KEY_0 => Some('0'),
-            _ => None,
+        let shift = !self.shift_keys_pressed.is_empty();
+        keymap::get_char(self.keyboard_layout, key_code, shift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use evdev::EventType;
+
+    use super::*;
+
+    fn key_event(key_code: KeyCode, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY.0, key_code.code(), value)
+    }
+
+    /// Press and release the key, returning any value read.
+    fn type_key(tag_reader: &mut TagReader, key_code: KeyCode) -> Option<String> {
+        let value = tag_reader.handle_event(key_event(key_code, 1));
+        assert!(tag_reader.handle_event(key_event(key_code, 0)).is_none());
+        value
+    }
+
+    fn tag_reader(terminator_keys: &[KeyCode]) -> TagReader {
+        TagReader::new(
+            KeyboardLayout::Us,
+            terminator_keys.iter().copied().collect(),
+        )
+    }
+
+    #[test]
+    fn test_tag_reader_returns_chars_on_terminator() {
+        let mut tag_reader = tag_reader(&[KeyCode::KEY_ENTER]);
+
+        for key_code in [KeyCode::KEY_1, KeyCode::KEY_2, KeyCode::KEY_A] {
+            assert!(type_key(&mut tag_reader, key_code).is_none());
         }
+        assert_eq!(
+            type_key(&mut tag_reader, KeyCode::KEY_ENTER).as_deref(),
+            Some("12a")
+        );
+    }
+
+    #[test]
+    fn test_tag_reader_applies_shift() {
+        let mut tag_reader = tag_reader(&[KeyCode::KEY_ENTER]);
+
+        tag_reader.handle_event(key_event(KeyCode::KEY_LEFTSHIFT, 1));
+        type_key(&mut tag_reader, KeyCode::KEY_A);
+        tag_reader.handle_event(key_event(KeyCode::KEY_LEFTSHIFT, 0));
+        type_key(&mut tag_reader, KeyCode::KEY_B);
+
+        assert_eq!(
+            type_key(&mut tag_reader, KeyCode::KEY_ENTER).as_deref(),
+            Some("Ab")
+        );
+    }
+
+    #[test]
+    fn test_tag_reader_skips_empty_input_between_terminators() {
+        let mut tag_reader = tag_reader(&[KeyCode::KEY_ENTER, KeyCode::KEY_TAB]);
+
+        type_key(&mut tag_reader, KeyCode::KEY_7);
+        assert_eq!(
+            type_key(&mut tag_reader, KeyCode::KEY_ENTER).as_deref(),
+            Some("7")
+        );
+        assert!(type_key(&mut tag_reader, KeyCode::KEY_TAB).is_none());
+        assert!(type_key(&mut tag_reader, KeyCode::KEY_ENTER).is_none());
+    }
+
+    const WINDOW: Duration = Duration::from_millis(2000);
+
+    fn ms(milliseconds: u64) -> Duration {
+        Duration::from_millis(milliseconds)
+    }
+
+    #[test]
+    fn test_duplicate_filter_drops_repeat_within_window() {
+        let start = Instant::now();
+        let mut filter = DuplicateTagFilter::new(WINDOW, false);
+
+        assert!(filter.accept("1234", start));
+        assert!(!filter.accept("1234", start + ms(500)));
+        assert!(!filter.accept("1234", start + ms(1999)));
+    }
+
+    #[test]
+    fn test_duplicate_filter_extends_window_on_repeat() {
+        let start = Instant::now();
+        let mut filter = DuplicateTagFilter::new(WINDOW, false);
+
+        assert!(filter.accept("1234", start));
+        // Each repeat extends the window, so the tag held at the reader is
+        // not reported again ...
+        for seconds in 1..=10 {
+            assert!(!filter.accept("1234", start + ms(seconds * 1500)));
+        }
+        // ... until it has been away for the whole window.
+        assert!(filter.accept("1234", start + ms(15000) + WINDOW));
+    }
+
+    #[test]
+    fn test_duplicate_filter_reannounces_after_window() {
+        let start = Instant::now();
+        let mut filter = DuplicateTagFilter::new(WINDOW, true);
+
+        assert!(filter.accept("1234", start));
+        assert!(!filter.accept("1234", start + ms(1500)));
+        assert!(filter.accept("1234", start + ms(2000)));
+        assert!(!filter.accept("1234", start + ms(3500)));
+        assert!(filter.accept("1234", start + ms(4000)));
+    }
+
+    #[test]
+    fn test_duplicate_filter_accepts_different_tag() {
+        let start = Instant::now();
+        for reannounce in [false, true] {
+            let mut filter = DuplicateTagFilter::new(WINDOW, reannounce);
+
+            assert!(filter.accept("1234", start));
+            assert!(filter.accept("5678", start + ms(1)));
+            assert!(filter.accept("1234", start + ms(2)));
+        }
+    }
+
+    #[test]
+    fn test_duplicate_filter_with_zero_window_accepts_everything() {
+        let start = Instant::now();
+        for reannounce in [false, true] {
+            let mut filter = DuplicateTagFilter::new(Duration::ZERO, reannounce);
+
+            assert!(filter.accept("1234", start));
+            assert!(filter.accept("1234", start));
+            assert!(filter.accept("1234", start + ms(1)));
+        }
+    }
+
+    #[test]
+    fn test_parse_terminator_keys() {
+        let names = ["KEY_ENTER".to_string(), "KEY_TAB".to_string()];
+        let keys = parse_terminator_keys(&names).unwrap();
+        assert!(keys.contains(&KeyCode::KEY_ENTER));
+        assert!(keys.contains(&KeyCode::KEY_TAB));
+
+        assert!(parse_terminator_keys(&["KEY_NONSENSE".to_string()]).is_err());
     }
 }
@@ -0,0 +1,178 @@
+/*
+ * Copyright 2022-2025 Jochen Kupperschmidt
+ * License: MIT
+ */
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use rodio::buffer::SamplesBuffer;
+use tempfile::TempDir;
+
+use crate::audio;
+
+/// Maximum time the text-to-speech command may take
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval at which the command is checked for having finished
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Synthesizes speech by running an external program (e.g. espeak-ng or
+/// piper) that writes a WAV file.
+///
+/// In the command's arguments, `{text}` is replaced with the text to
+/// speak and `{output}` with the path of the WAV file to write.
+pub(crate) struct SpeechSynthesizer {
+    command: Vec<String>,
+    /// Private directory (only accessible by the current user) for the
+    /// WAV files, removed when the synthesizer is dropped
+    output_directory: TempDir,
+    counter: AtomicU32,
+}
+
+impl SpeechSynthesizer {
+    pub(crate) fn new(command: Vec<String>) -> Result<Self> {
+        ensure!(!command.is_empty(), "No text-to-speech command configured");
+
+        Ok(Self {
+            command,
+            output_directory: create_output_directory()?,
+            counter: AtomicU32::new(0),
+        })
+    }
+
+    pub(crate) fn synthesize(&self, text: &str) -> Result<SamplesBuffer> {
+        let output_path = self.next_output_path();
+        let output = output_path.to_string_lossy();
+
+        let args: Vec<String> = self
+            .command
+            .iter()
+            .map(|arg| arg.replace("{text}", text).replace("{output}", &output))
+            .collect();
+
+        let result = run_command(&args, COMMAND_TIMEOUT).and_then(|status| {
+            ensure!(
+                status.success(),
+                "Text-to-speech command failed ({})",
+                status
+            );
+            audio::decode_file(&output_path).context("Could not decode synthesized speech")
+        });
+
+        let _ = fs::remove_file(&output_path);
+        result
+    }
+
+    fn next_output_path(&self) -> PathBuf {
+        let number = self.counter.fetch_add(1, Ordering::Relaxed);
+        self.output_directory
+            .path()
+            .join(format!("speech-{}.wav", number))
+    }
+}
+
+/// Run the command, killing it if it does not finish in time (so that
+/// a hanging program cannot hold up playback and shutdown).
+fn run_command(args: &[String], timeout: Duration) -> Result<ExitStatus> {
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .spawn()
+        .with_context(|| format!("Could not run text-to-speech command {}", args[0]))?;
+
+    let started_at = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if started_at.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!(
+                "Text-to-speech command did not finish within {:?}",
+                timeout
+            ));
+        }
+
+        sleep(COMMAND_POLL_INTERVAL);
+    }
+}
+
+fn create_output_directory() -> Result<TempDir> {
+    tempfile::Builder::new()
+        .prefix("verbleiber-tts-")
+        .permissions(fs::Permissions::from_mode(0o700))
+        .tempdir()
+        .context("Could not create directory for synthesized speech")
+}
+
+/// Fill in the placeholders `{screen_name}` and `{whereabouts_name}`.
+pub(crate) fn render_template(
+    template: &str,
+    screen_name: Option<&str>,
+    whereabouts_name: Option<&str>,
+) -> String {
+    template
+        .replace("{screen_name}", screen_name.unwrap_or_default())
+        .replace("{whereabouts_name}", whereabouts_name.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_directory_is_private() {
+        let directory = create_output_directory().unwrap();
+        let path = directory.path().to_path_buf();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        drop(directory);
+        assert!(!path.exists());
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_command_exit_status_is_returned() {
+        let status = run_command(&args(&["true"]), COMMAND_TIMEOUT).unwrap();
+        assert!(status.success());
+
+        let status = run_command(&args(&["false"]), COMMAND_TIMEOUT).unwrap();
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn test_hanging_command_is_killed() {
+        let started_at = Instant::now();
+
+        let result = run_command(&args(&["sleep", "10"]), Duration::from_millis(100));
+
+        assert!(result.is_err());
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_template_placeholders_are_filled_in() {
+        let text = render_template(
+            "Bye, {screen_name}, enjoy {whereabouts_name}!",
+            Some("Alice"),
+            Some("lunch"),
+        );
+        assert_eq!(text, "Bye, Alice, enjoy lunch!");
+
+        let text = render_template("Hello, {screen_name}!", None, None);
+        assert_eq!(text, "Hello, !");
+    }
+}